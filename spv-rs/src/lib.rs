@@ -46,23 +46,25 @@
 /// Set of functions to calculate the position of either primary or companion bodies for diffrent usecases.
 /// All outputs are in the cartesian coordinate system.
 pub mod position {
-    use super::common::semi_parameter;
-    use super::common::true_anomaly;
-    use super::coordinate_transforms::euler_angle_transformations;
+    use super::astrometry::{propagate_epoch, AstrometricParameters};
+    use super::common::PARSEC;
+    use super::coordinate_transforms::icrs_to_galactic;
+    use super::orbit::{Orbit, OrbitalElements};
     use glam::f32::Vec3;
     use glam::f64::{DVec2, DVec3};
 
     /// Position of a single celestial object relative to the sun.
     /// Can be used in conjuction with companion functions to place a twobody system relative to the sun.
     /// parallax is in mas (milliarcseconds), right_ascension is in degrees and declination in degrees.
+    /// The frame is right-handed with z towards the north celestial pole, so a positive declination gives a positive z (see CHANGELOG.md for 0.8.0).
     /// Output is a 3-dimensional vector with x, y and z in that order all in meters.
     pub fn position(parallax: f64, right_ascension: f64, declination: f64) -> DVec3 {
         let distance = 1. / (parallax / 1000.);
 
-        let distnace_si = distance * (3.0856778570831 * 10_f64.powf(16.));
+        let distnace_si = distance * PARSEC;
 
         let right_ascension_rad = right_ascension.to_radians();
-        let declination_rad = (declination + 90.).to_radians();
+        let declination_rad = (90. - declination).to_radians();
 
         let x = distnace_si * right_ascension_rad.cos() * declination_rad.sin();
 
@@ -73,14 +75,39 @@ pub mod position {
         DVec3::new(x, y, z)
     }
 
+    /// Same as [`position`] but for astrometric parameters given at epoch instead of J2000.
+    /// The parameters are first propagated to target_epoch with [`propagate_epoch`], both epochs are in Julian years
+    /// (e.g. 2016.0 for Gaia DR3, 1991.25 for Hipparcos and 2000.0 for J2000).
+    /// Output is a 3-dimensional vector with x, y and z in that order all in meters at target_epoch.
+    pub fn position_at_epoch(
+        astrometry: AstrometricParameters,
+        epoch: f64,
+        target_epoch: f64,
+    ) -> DVec3 {
+        let propagated = propagate_epoch(astrometry, epoch, target_epoch);
+
+        position(
+            propagated.parallax,
+            propagated.right_ascension,
+            propagated.declination,
+        )
+    }
+
+    /// Position of a single celestial object relative to the sun in the galactic frame.
+    /// parallax is in mas (milliarcseconds), right_ascension is in degrees and declination in degrees.
+    /// Output is a 3-dimensional vector with x (towards the galactic center), y (towards galactic rotation) and z (towards the north galactic pole) all in meters.
+    pub fn galactic_position(parallax: f64, right_ascension: f64, declination: f64) -> DVec3 {
+        icrs_to_galactic(position(parallax, right_ascension, declination))
+    }
+
     /// Same as [position::position] but with a f32 vector returned if you need that.
     pub fn position_f32(parallax: f32, right_ascension: f32, declination: f32) -> Vec3 {
         let distance = 1. / (parallax / 1000.);
 
-        let distnace_si = distance * (3.0856778570831 * 10_f32.powf(16.));
+        let distnace_si = distance * (PARSEC as f32);
 
         let right_ascension_rad = right_ascension.to_radians();
-        let declination_rad = (declination + 90.).to_radians();
+        let declination_rad = (90. - declination).to_radians();
 
         let x = distnace_si * right_ascension_rad.cos() * declination_rad.sin();
 
@@ -94,7 +121,7 @@ pub mod position {
     /// Position on the surface of a sphere with radius in meters.
     pub fn position_surface(radius: f64, right_ascension: f64, declination: f64) -> DVec3 {
         let right_ascension_rad = right_ascension.to_radians();
-        let declination_rad = (declination + 90.).to_radians();
+        let declination_rad = (90. - declination).to_radians();
 
         let x = radius * right_ascension_rad.cos() * declination_rad.sin();
 
@@ -110,21 +137,14 @@ pub mod position {
     /// Output is a 2-dimensional vector with x and y in that order all in meters. We only need a 2-dimensional vector here
     /// due to the fact that everything is on a plane in 2D.
     pub fn companion_position(a: f64, e: f64, period: f64, t_p: f64) -> DVec2 {
-        //Prep Values
-        let p = semi_parameter(a, e);
-        let v = true_anomaly(e, period, t_p);
-
-        //Position of Companion in ellipse base
-        let x = (p * v.cos()) / (1. + e * v.cos());
-        let y = (p * v.sin()) / (1. + e * v.cos());
-
-        DVec2::new(x, y)
+        Orbit::new(OrbitalElements::unchecked(a, e, period, 0., 0., 0.))
+            .orbital_plane_position_at(t_p)
     }
 
     /// Position of the companion star in a twobody system with rotation relative to the earth/sun plane applied.
     /// a is semi major-axis in au, e is eccentricity, period is in years, t_p is time since periastron in years,
     /// lotn is Longitude of the node (Omega) in degrees, aop is Argument of periastron (omega) in degrees and finally i is the Inclination in degrees.
-    /// Output is a 3-dimensional vector with x, y and z in that order all in meters.
+    /// Output is a 3-dimensional vector with x, y and z in that order all in meters. See [`super::orbit::Orbit::position_at`].
     pub fn companion_relative_position(
         a: f64,
         e: f64,
@@ -134,55 +154,115 @@ pub mod position {
         aop: f64,
         i: f64,
     ) -> DVec3 {
-        //Prep Values
-        let p = semi_parameter(a, e);
-        let v = true_anomaly(e, period, t_p);
+        Orbit::new(OrbitalElements::unchecked(a, e, period, lotn, aop, i)).position_at(t_p)
+    }
 
-        //Position of Companion in ellipse base
-        let x = (p * v.cos()) / (1. + e * v.cos());
-        let y = (p * v.sin()) / (1. + e * v.cos());
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn positive_declination_has_positive_z() {
+            //Before the polar angle was changed to 90 - declination z had the opposite sign
+            let star = position(100., 30., 45.);
+            let star_f32 = position_f32(100., 30., 45.);
+            let surface = position_surface(1., 30., 45.);
+
+            assert!(star.z > 0.);
+            assert!(star_f32.z > 0.);
+            assert!(surface.z > 0.);
+            assert!(position(100., 30., -45.).z < 0.);
+        }
 
-        //Ellipse base
-        let euler_angle_transformations = euler_angle_transformations(lotn, aop, i).to_cols_array();
-        let x1 = euler_angle_transformations[0];
-        let x2 = euler_angle_transformations[1];
-        let x3 = euler_angle_transformations[2];
+        #[test]
+        fn position_axes_and_distance() {
+            let vernal_equinox = position(1000., 0., 0.);
+            assert!((vernal_equinox / PARSEC - DVec3::X).length() < 1e-15);
 
-        let y1 = euler_angle_transformations[3];
-        let y2 = euler_angle_transformations[4];
-        let y3 = euler_angle_transformations[5];
+            let ra_six_hours = position(1000., 90., 0.);
+            assert!((ra_six_hours / PARSEC - DVec3::Y).length() < 1e-15);
 
-        //Position in original base
-        let companion_position_x = (x1 * x) + (y1 * y);
-        let companion_position_y = (x2 * x) + (y2 * y);
-        let companion_position_z = (x3 * x) + (y3 * y);
+            let north_pole = position_surface(2., 123., 90.);
+            assert!((north_pole - DVec3::new(0., 0., 2.)).length() < 1e-12);
 
-        DVec3::new(
-            companion_position_x,
-            companion_position_y,
-            companion_position_z,
-        )
+            let star = position(4.2, 250., -30.);
+            assert!((star.length() / (PARSEC * 1000. / 4.2) - 1.).abs() < 1e-14);
+        }
+
+        #[test]
+        fn galactic_position_of_the_galactic_center() {
+            //Hipparcos definition of the galactic center direction
+            let center = galactic_position(1., 266.40499, -28.93617);
+
+            assert!((center.normalize() - DVec3::X).length() < 1e-6);
+        }
     }
 }
 
 /// Set of functions to calculate the velocity of either primary or companion bodies for diffrent usecases.
 /// All outputs are in the cartesian coordinate system.
 pub mod velocity {
+    use super::astrometry::normal_triad;
+    use super::astrometry::{propagate_epoch, AstrometricParameters};
+    use super::common::is_parabolic;
     use super::common::radius;
-    use super::common::semi_parameter;
+    use super::common::signed_semi_major_axis;
     use super::common::specific_mechanical_energy;
     use super::common::standard_gravitational_parameter;
-    use super::common::true_anomaly;
-    use super::coordinate_transforms::euler_angle_transformations;
-    use super::position::position;
+    use super::common::PARSEC;
+    use super::coordinate_transforms::icrs_to_galactic;
+    use super::orbit::{Orbit, OrbitalElements};
+    use super::time::JULIAN_YEAR;
     use glam::f64::{DVec2, DVec3};
 
+    /// Whether the right ascension part of a proper motion is the rate of change of right ascension (pmRA)
+    /// or the rate of change of right ascension multiplied by cos(declination) (pmRA*, used by Hipparcos and Gaia).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ProperMotionRa {
+        Pmra,
+        PmraCosDec,
+    }
+
+    /// Unit of the proper motion inputs.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ProperMotionUnit {
+        ArcsecondsPerYear,
+        MilliarcsecondsPerYear,
+    }
+
+    /// Convention for the proper motion inputs of [`velocity_with_convention`] and [`tangential_velocity`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ProperMotionConvention {
+        pub ra: ProperMotionRa,
+        pub unit: ProperMotionUnit,
+    }
+
+    impl ProperMotionConvention {
+        /// The convention used by [`velocity`] and the rest of `spv-rs`, pmRA in as/yr (arcseconds per year).
+        pub const SPV: Self = Self {
+            ra: ProperMotionRa::Pmra,
+            unit: ProperMotionUnit::ArcsecondsPerYear,
+        };
+
+        /// The convention used by the Gaia archive, pmRA* in mas/yr (milliarcseconds per year).
+        pub const GAIA: Self = Self {
+            ra: ProperMotionRa::PmraCosDec,
+            unit: ProperMotionUnit::MilliarcsecondsPerYear,
+        };
+
+        /// Creates a new proper motion convention.
+        pub fn new(ra: ProperMotionRa, unit: ProperMotionUnit) -> Self {
+            Self { ra, unit }
+        }
+    }
+
     /// Velocity of a single celestial object relative to the sun.
     /// Can be used in conjuction with companion functions to place a twobody system relative to the sun.
     /// parallax is in mas (milliarcseconds), right_ascension is in degrees and declination in degrees,
-    /// proper_motion_ra is the right ascension part of the proper motion variable in as (arcseconds),
-    /// proper_motion_dec is the declination part of the proper motion variable in as (arcseconds) and
-    /// radial_velocity is in km/s.
+    /// proper_motion_ra is the rate of change of right ascension (not multiplied by cos(declination)) in as/yr (arcseconds per year),
+    /// proper_motion_dec is in as/yr (arcseconds per year) and radial_velocity is in km/s.
+    /// Use [`velocity_with_convention`] for proper motions given as pmRA* or in mas/yr.
+    /// Proper motions are per Julian year of 365.25 days (see CHANGELOG.md for 0.8.0, older versions used the tropical year).
     /// Output is a 3-dimensional vector with x, y and z in that order all in meters/second.
     pub fn velocity(
         parallax: f64,
@@ -192,56 +272,113 @@ pub mod velocity {
         proper_motion_dec: f64,
         radial_velocity: f64,
     ) -> DVec3 {
-        let distance = 1. / (parallax / 1000.);
-
-        //SI
-        let distnace_si = distance * (3.0856778570831 * 10_f64.powf(16.));
-        let radial_velocity_si = radial_velocity * 1000.;
-
-        let proper_motion_x = distnace_si
-            * (((right_ascension + ((proper_motion_ra * 0.00027777777777778) / 31556926.))
-                .to_radians())
-            .cos())
-            * ((((declination + ((proper_motion_dec * 0.00027777777777778) / 31556926.)) + 90.)
-                .to_radians())
-            .sin());
-
-        let proper_motion_y = distnace_si
-            * (((right_ascension + ((proper_motion_ra * 0.00027777777777778) / 31556926.))
-                .to_radians())
-            .sin())
-            * ((((declination + ((proper_motion_dec * 0.00027777777777778) / 31556926.)) + 90.)
-                .to_radians())
-            .sin());
-
-        let proper_motion_z = distnace_si
-            * ((((declination + ((proper_motion_dec * 0.00027777777777778) / 31556926.)) + 90.)
-                .to_radians())
-            .cos());
+        velocity_with_convention(
+            parallax,
+            right_ascension,
+            declination,
+            proper_motion_ra,
+            proper_motion_dec,
+            radial_velocity,
+            ProperMotionConvention::SPV,
+        )
+    }
 
-        let position = position(parallax, right_ascension, declination).to_array();
+    /// Same as [`velocity`] but with the proper motion convention given explicitly, e.g. [`ProperMotionConvention::GAIA`] for Gaia catalog columns.
+    /// The velocity is built analytically from the local normal triad so it keeps its accuracy for nearby and fast stars.
+    /// Output is a 3-dimensional vector with x, y and z in that order all in meters/second.
+    pub fn velocity_with_convention(
+        parallax: f64,
+        right_ascension: f64,
+        declination: f64,
+        proper_motion_ra: f64,
+        proper_motion_dec: f64,
+        radial_velocity: f64,
+        convention: ProperMotionConvention,
+    ) -> DVec3 {
+        let triad = normal_triad(right_ascension, declination);
+
+        tangential_velocity(
+            parallax,
+            right_ascension,
+            declination,
+            proper_motion_ra,
+            proper_motion_dec,
+            convention,
+        ) + (triad.z_axis * (radial_velocity * 1000.))
+    }
 
-        let x = position[0];
-        let y = position[1];
-        let z = position[2];
+    /// Tangential (proper motion) part of the velocity of a single celestial object relative to the sun,
+    /// see [`velocity_with_convention`] for the inputs. The velocity is p * v_ra + q * v_dec where p and q are the
+    /// unit vectors towards increasing right ascension and declination from [`normal_triad`].
+    /// Output is a 3-dimensional vector with x, y and z in that order all in meters/second.
+    pub fn tangential_velocity(
+        parallax: f64,
+        right_ascension: f64,
+        declination: f64,
+        proper_motion_ra: f64,
+        proper_motion_dec: f64,
+        convention: ProperMotionConvention,
+    ) -> DVec3 {
+        //SI
+        let distance_si = PARSEC / (parallax / 1000.);
+        let unit_to_as = match convention.unit {
+            ProperMotionUnit::ArcsecondsPerYear => 1.,
+            ProperMotionUnit::MilliarcsecondsPerYear => 0.001,
+        };
+        //as/yr to rad/s
+        let pm_to_si = unit_to_as * (1. / 3600_f64).to_radians() / JULIAN_YEAR;
 
-        let proper_motion_vector_x = proper_motion_x - x;
-        let proper_motion_vector_y = proper_motion_y - y;
-        let proper_motion_vector_z = proper_motion_z - z;
+        let proper_motion_ra_star = match convention.ra {
+            ProperMotionRa::Pmra => proper_motion_ra * declination.to_radians().cos(),
+            ProperMotionRa::PmraCosDec => proper_motion_ra,
+        };
 
-        let normalized_vector_x = x / (x.powf(2.) + y.powf(2.) + z.powf(2.)).sqrt();
-        let normalized_vector_y = y / (x.powf(2.) + y.powf(2.) + z.powf(2.)).sqrt();
-        let normalized_vector_z = z / (x.powf(2.) + y.powf(2.) + z.powf(2.)).sqrt();
+        let triad = normal_triad(right_ascension, declination);
 
-        let radial_velocity_vector_x = normalized_vector_x * radial_velocity_si;
-        let radial_velocity_vector_y = normalized_vector_y * radial_velocity_si;
-        let radial_velocity_vector_z = normalized_vector_z * radial_velocity_si;
+        (triad.x_axis * (proper_motion_ra_star * pm_to_si * distance_si))
+            + (triad.y_axis * (proper_motion_dec * pm_to_si * distance_si))
+    }
 
-        let x_v = radial_velocity_vector_x + proper_motion_vector_x;
-        let y_v = radial_velocity_vector_y + proper_motion_vector_y;
-        let z_v = radial_velocity_vector_z + proper_motion_vector_z;
+    /// Heliocentric space velocity UVW of a single celestial object in the galactic frame, inputs are the same as for [`velocity`].
+    /// U is positive towards the galactic center, V in the direction of galactic rotation and W towards the north galactic pole.
+    /// Output is a 3-dimensional vector with U, V and W in that order all in km/s.
+    pub fn uvw(
+        parallax: f64,
+        right_ascension: f64,
+        declination: f64,
+        proper_motion_ra: f64,
+        proper_motion_dec: f64,
+        radial_velocity: f64,
+    ) -> DVec3 {
+        icrs_to_galactic(velocity(
+            parallax,
+            right_ascension,
+            declination,
+            proper_motion_ra,
+            proper_motion_dec,
+            radial_velocity,
+        )) / 1000.
+    }
 
-        DVec3::new(x_v, y_v, z_v)
+    /// Same as [`velocity`] but for astrometric parameters given at epoch instead of J2000.
+    /// The parameters are first propagated to target_epoch with [`propagate_epoch`], both epochs are in Julian years
+    /// (e.g. 2016.0 for Gaia DR3, 1991.25 for Hipparcos and 2000.0 for J2000).
+    /// Output is a 3-dimensional vector with x, y and z in that order all in meters/second at target_epoch.
+    pub fn velocity_at_epoch(
+        astrometry: AstrometricParameters,
+        epoch: f64,
+        target_epoch: f64,
+    ) -> DVec3 {
+        let propagated = propagate_epoch(astrometry, epoch, target_epoch);
+
+        velocity(
+            propagated.parallax,
+            propagated.right_ascension,
+            propagated.declination,
+            propagated.proper_motion_ra,
+            propagated.proper_motion_dec,
+            propagated.radial_velocity,
+        )
     }
 
     /// Velocity of the companion star in a twobody system with no rotation applied.
@@ -249,22 +386,14 @@ pub mod velocity {
     /// Output is a 2-dimensional vector with x and y in that order all in meters/second. We only need a 2-dimensional vector here
     /// due to the fact that everything is on a plane in 2D.
     pub fn companion_velocity(a: f64, e: f64, period: f64, t_p: f64) -> DVec2 {
-        //Prep Values
-        let mu = standard_gravitational_parameter(a, period);
-        let p = semi_parameter(a, e);
-        let v = true_anomaly(e, period, t_p);
-
-        //Velocity of Companion in ellipse base
-        let x_v = (0. - ((mu / p).sqrt())) * (v.sin());
-        let y_v = ((mu / p).sqrt()) * (e + (v.cos()));
-
-        DVec2::new(x_v, y_v)
+        Orbit::new(OrbitalElements::unchecked(a, e, period, 0., 0., 0.))
+            .orbital_plane_velocity_at(t_p)
     }
 
     /// Velocity of the companion star in a twobody system with rotation relative to the earth/sun plane applied.
     /// a is semi major-axis in au, e is eccentricity, period is in years, t_p is time since periastron in years,
     /// lotn is Longitude of the node (Omega) in degrees, aop is Argument of periastron (omega) in degrees and finally i is the Inclination in degrees.
-    /// Output is a 3-dimensional vector with x, y and z in that order all in meters/second.
+    /// Output is a 3-dimensional vector with x, y and z in that order all in meters/second. See [`super::orbit::Orbit::velocity_at`].
     pub fn companion_relative_velocity(
         a: f64,
         e: f64,
@@ -274,338 +403,6123 @@ pub mod velocity {
         aop: f64,
         i: f64,
     ) -> DVec3 {
-        //Prep Values
+        Orbit::new(OrbitalElements::unchecked(a, e, period, lotn, aop, i)).velocity_at(t_p)
+    }
+
+    /// Just the companion velocity but as a value and not coordinates.
+    pub fn companion_velocity_value(a: f64, e: f64, period: f64, t_p: f64) -> f64 {
         let mu = standard_gravitational_parameter(a, period);
-        let p = semi_parameter(a, e);
-        let v = true_anomaly(e, period, t_p);
+        let r = radius(a, e, period, t_p);
+
+        //Vis-viva, the energy is zero for parabolic orbits
+        let epsilon = if is_parabolic(e) {
+            0.
+        } else {
+            specific_mechanical_energy(signed_semi_major_axis(a, e), period)
+        };
 
-        //Velocity of Companion in ellipse base
-        let x_v = (0. - ((mu / p).sqrt())) * (v.sin());
-        let y_v = ((mu / p).sqrt()) * (e + (v.cos()));
+        (2. * ((mu / r) + epsilon)).sqrt()
+    }
 
-        //Ellipse base
-        let euler_angle_transformations = euler_angle_transformations(lotn, aop, i).to_cols_array();
-        let x1 = euler_angle_transformations[0];
-        let x2 = euler_angle_transformations[1];
-        let x3 = euler_angle_transformations[2];
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::position::position;
+
+        #[test]
+        fn positive_declination_motion_has_positive_z() {
+            //A star on the celestial equator moving north
+            let moving_north = velocity(100., 30., 0., 0., 1., 0.);
+            assert!(moving_north.z > 0.);
+
+            //A star at positive declination receding from the sun
+            let receding = velocity(100., 30., 45., 0., 0., 10.);
+            assert!(receding.z > 0.);
+            assert!((receding.length() - 10000.).abs() < 1e-9);
+        }
 
-        let y1 = euler_angle_transformations[3];
-        let y2 = euler_angle_transformations[4];
-        let y3 = euler_angle_transformations[5];
+        #[test]
+        fn uvw_of_a_star_at_the_galactic_poles() {
+            //Receding from the sun towards the north galactic pole is positive W
+            let north = uvw(10., 192.85948, 27.12825, 0., 0., 10.);
+            assert!((north - DVec3::new(0., 0., 10.)).length() < 1e-6);
 
-        //Velocity in original base
-        let companion_velocity_x = (x1 * x_v) + (y1 * y_v);
-        let companion_velocity_y = (x2 * x_v) + (y2 * y_v);
-        let companion_velocity_z = (x3 * x_v) + (y3 * y_v);
+            //Receding from the sun towards the galactic center is positive U
+            let center = uvw(10., 266.40499, -28.93617, 0., 0., 25.);
+            assert!((center - DVec3::new(25., 0., 0.)).length() < 1e-5);
+        }
 
-        DVec3::new(
-            companion_velocity_x,
-            companion_velocity_y,
-            companion_velocity_z,
-        )
+        #[test]
+        fn uvw_speed_matches_total_velocity() {
+            let space = uvw(50., 120., -20., 0.3, -0.2, 35.);
+            let total = velocity(50., 120., -20., 0.3, -0.2, 35.);
+
+            assert!((space.length() * 1000. - total.length()).abs() < 1e-6);
+        }
+
+        #[test]
+        fn proper_motion_uses_the_julian_year() {
+            //1 as/yr at 1 pc is 1 au per Julian year
+            let tangential = velocity(1000., 10., 20., 0., 1., 0.);
+            let au_per_julian_year = 149597870700. / JULIAN_YEAR;
+
+            assert!((tangential.length() / au_per_julian_year - 1.).abs() < 1e-6);
+        }
+
+        #[test]
+        fn gaia_convention_matches_spv_convention() {
+            let declination: f64 = -48.3;
+            let spv = velocity(21., 143., declination, 0.12, -0.34, 17.);
+            let gaia = velocity_with_convention(
+                21.,
+                143.,
+                declination,
+                120. * declination.to_radians().cos(),
+                -340.,
+                17.,
+                ProperMotionConvention::GAIA,
+            );
+
+            assert!((spv - gaia).length() < 1e-9);
+            assert!((spv.dot(position(21., 143., declination).normalize()) - 17000.).abs() < 1e-9);
+        }
     }
+}
 
-    /// Just the companion velocity but as a value and not coordinates.
-    pub fn companion_velocity_value(a: f64, e: f64, period: f64, t_p: f64) -> f64 {
-        let mu = standard_gravitational_parameter(a, period);
-        let epsilon = specific_mechanical_energy(a, e);
-        let r = radius(a, e, period, t_p);
+/// Close encounters (flybys) between two stars, or between a star and the sun, from the cartesian states given by
+/// [`super::position::position`] and [`super::velocity::velocity`]. A state is a tuple of position in meters and velocity in meters/second,
+/// the sun is (DVec3::ZERO, DVec3::ZERO). Times are in Julian years relative to the epoch of the states, negative for past encounters.
+pub mod encounter {
+    use super::astrometry::AstrometricParameters;
+    use super::galactic_potential::{leapfrog_step, MilkyWayPotential};
+    use super::galactocentric::{
+        galactocentric_position, galactocentric_velocity, GalactocentricFrame,
+    };
+    use super::position::position_at_epoch;
+    use super::time::JULIAN_YEAR;
+    use super::velocity::velocity_at_epoch;
+    use glam::f64::DVec3;
+    use rayon::prelude::*;
+    use std::collections::HashMap;
+
+    /// Closest approach between two stars, time is in years, distance in meters and relative_velocity in meters/second at the encounter.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Encounter {
+        pub time: f64,
+        pub distance: f64,
+        pub relative_velocity: f64,
+    }
 
-        (2. * ((mu / r) + epsilon)).sqrt()
+    //Time in seconds of the closest approach of a relative state moving in a straight line
+    fn linear_time(relative_position: DVec3, relative_velocity: DVec3) -> f64 {
+        let speed_sq = relative_velocity.length_squared();
+        if speed_sq == 0. {
+            return 0.;
+        }
+
+        -relative_position.dot(relative_velocity) / speed_sq
+    }
+
+    /// Closest approach of star_b to star_a assuming that both move in straight lines with constant velocity.
+    /// Good for encounters within a few Myr, use [`galactic_encounter`] further away in time.
+    pub fn linear_encounter(star_a: (DVec3, DVec3), star_b: (DVec3, DVec3)) -> Encounter {
+        let relative_position = star_b.0 - star_a.0;
+        let relative_velocity = star_b.1 - star_a.1;
+
+        let time = linear_time(relative_position, relative_velocity);
+
+        Encounter {
+            time: time / JULIAN_YEAR,
+            distance: (relative_position + (relative_velocity * time)).length(),
+            relative_velocity: relative_velocity.length(),
+        }
+    }
+
+    /// Closest approach of star_b to star_a when both orbit in the galactic potential, searched from the epoch of the states up to time_span years
+    /// (negative to search the past) with steps leapfrog steps. The gravity between the two stars is neglected.
+    /// The closest approach is refined inside the best step with a linear approximation.
+    /// Returns `None` if steps is zero or time_span is not finite.
+    pub fn galactic_encounter(
+        star_a: (DVec3, DVec3),
+        star_b: (DVec3, DVec3),
+        potential: &MilkyWayPotential,
+        frame: &GalactocentricFrame,
+        time_span: f64,
+        steps: usize,
+    ) -> Option<Encounter> {
+        if steps == 0 || !time_span.is_finite() {
+            return None;
+        }
+
+        let time_step = time_span * JULIAN_YEAR / steps as f64;
+
+        let mut state_a = (
+            galactocentric_position(star_a.0, frame),
+            galactocentric_velocity(star_a.1, frame),
+        );
+        let mut state_b = (
+            galactocentric_position(star_b.0, frame),
+            galactocentric_velocity(star_b.1, frame),
+        );
+
+        let mut closest = (0., state_b.0 - state_a.0, state_b.1 - state_a.1);
+        for step in 1..=steps {
+            state_a = leapfrog_step(potential, state_a, time_step);
+            state_b = leapfrog_step(potential, state_b, time_step);
+
+            let relative_position = state_b.0 - state_a.0;
+            if relative_position.length() < closest.1.length() {
+                closest = (
+                    step as f64 * time_step,
+                    relative_position,
+                    state_b.1 - state_a.1,
+                );
+            }
+        }
+
+        //Refine within one step on either side of the closest sampled point
+        let (time, relative_position, relative_velocity) = closest;
+        let offset = linear_time(relative_position, relative_velocity)
+            .clamp(-time_step.abs(), time_step.abs());
+
+        Some(Encounter {
+            time: (time + offset) / JULIAN_YEAR,
+            distance: (relative_position + (relative_velocity * offset)).length(),
+            relative_velocity: relative_velocity.length(),
+        })
+    }
+
+    /// Closest approach of star_b to star_a moving in straight lines, limited to the time window from start to end in years.
+    /// The window may be given in either order, e.g. from 0 to -5e6 to only search the past.
+    pub fn linear_encounter_within(
+        star_a: (DVec3, DVec3),
+        star_b: (DVec3, DVec3),
+        start: f64,
+        end: f64,
+    ) -> Encounter {
+        let relative_position = star_b.0 - star_a.0;
+        let relative_velocity = star_b.1 - star_a.1;
+
+        let time = linear_time(relative_position, relative_velocity)
+            .max(start.min(end) * JULIAN_YEAR)
+            .min(start.max(end) * JULIAN_YEAR);
+
+        Encounter {
+            time: time / JULIAN_YEAR,
+            distance: (relative_position + (relative_velocity * time)).length(),
+            relative_velocity: relative_velocity.length(),
+        }
+    }
+
+    /// Cartesian states of a whole catalog at target_epoch, computed in parallel. astrometry is referred to epoch (Julian years),
+    /// for example rows from [`super::input_data::parse_csv`] mapped to [`AstrometricParameters`].
+    pub fn catalog_states(
+        astrometry: &[AstrometricParameters],
+        epoch: f64,
+        target_epoch: f64,
+    ) -> std::vec::Vec<(DVec3, DVec3)> {
+        astrometry
+            .par_iter()
+            .map(|star| {
+                (
+                    position_at_epoch(*star, epoch, target_epoch),
+                    velocity_at_epoch(*star, epoch, target_epoch),
+                )
+            })
+            .collect()
+    }
+
+    /// All stars in states that pass within max_distance meters of the sun between start and end in years, assuming straight line motion.
+    /// Output is a vector of the index into states and the encounter, in the order of states.
+    pub fn sun_encounters(
+        states: &[(DVec3, DVec3)],
+        max_distance: f64,
+        start: f64,
+        end: f64,
+    ) -> std::vec::Vec<(usize, Encounter)> {
+        states
+            .par_iter()
+            .enumerate()
+            .filter_map(|(index, state)| {
+                let encounter =
+                    linear_encounter_within((DVec3::ZERO, DVec3::ZERO), *state, start, end);
+                (encounter.distance <= max_distance).then_some((index, encounter))
+            })
+            .collect()
+    }
+
+    //Paths whose box spans more cells than this along an axis are compared with every star instead of being put in the grid
+    const OVERSIZED_CELLS: f64 = 4.;
+
+    /// All pairs of stars in states that come within max_distance meters of each other between start and end in years, assuming straight line motion.
+    /// The straight paths of the stars during the window are binned in a uniform 3D grid so that only stars whose paths share a cell are compared,
+    /// and the cells are searched in parallel. Output is a vector of the two indices into states (smallest first) and the encounter, sorted by the indices.
+    /// States that are not finite (e.g. from a non-positive parallax) are skipped. Paths much longer than the typical path
+    /// (e.g. the huge velocities that tiny parallaxes give) are kept out of the grid and compared with every other star.
+    pub fn catalog_encounters(
+        states: &[(DVec3, DVec3)],
+        max_distance: f64,
+        start: f64,
+        end: f64,
+    ) -> std::vec::Vec<(usize, usize, Encounter)> {
+        //Bounding boxes of the paths grown by half the distance so that boxes of close pairs overlap, None for states that are not finite
+        let margin = DVec3::splat(max_distance / 2.);
+        let boxes: std::vec::Vec<Option<(DVec3, DVec3)>> = states
+            .par_iter()
+            .map(|(position, velocity)| {
+                let first = *position + (*velocity * start * JULIAN_YEAR);
+                let last = *position + (*velocity * end * JULIAN_YEAR);
+                let (min, max) = (first.min(last) - margin, first.max(last) + margin);
+                (min.is_finite() && max.is_finite()).then_some((min, max))
+            })
+            .collect();
+
+        //Cell size from the median size of the boxes
+        let mut sizes: std::vec::Vec<f64> = boxes
+            .par_iter()
+            .flatten()
+            .map(|(min, max)| (*max - *min).max_element())
+            .collect();
+        if sizes.is_empty() {
+            return std::vec::Vec::new();
+        }
+        sizes.par_sort_unstable_by(|a, b| a.total_cmp(b));
+        let cell_size = sizes[sizes.len() / 2].max(max_distance);
+
+        let cell = |point: DVec3| {
+            let index = (point / cell_size).floor();
+            (index.x as i64, index.y as i64, index.z as i64)
+        };
+        let overlap = |(min_i, max_i): (DVec3, DVec3), (min_j, max_j): (DVec3, DVec3)| {
+            !(max_i.cmplt(min_j).any() || max_j.cmplt(min_i).any())
+        };
+        let close_pair = |i: usize, j: usize| {
+            let encounter = linear_encounter_within(states[i], states[j], start, end);
+            (encounter.distance <= max_distance).then_some((i, j, encounter))
+        };
+
+        let mut grid: HashMap<(i64, i64, i64), std::vec::Vec<usize>> = HashMap::new();
+        let mut oversized = vec![false; states.len()];
+        for (index, bounds) in boxes.iter().enumerate() {
+            if let Some((min, max)) = bounds {
+                if (*max - *min).max_element() > OVERSIZED_CELLS * cell_size {
+                    oversized[index] = true;
+                    continue;
+                }
+
+                let low = cell(*min);
+                let high = cell(*max);
+                for x in low.0..=high.0 {
+                    for y in low.1..=high.1 {
+                        for z in low.2..=high.2 {
+                            grid.entry((x, y, z)).or_default().push(index);
+                        }
+                    }
+                }
+            }
+        }
+
+        let cells: std::vec::Vec<((i64, i64, i64), std::vec::Vec<usize>)> =
+            grid.into_iter().collect();
+
+        let mut encounters: std::vec::Vec<(usize, usize, Encounter)> = cells
+            .par_iter()
+            .flat_map_iter(|(key, members)| {
+                let mut found = std::vec::Vec::new();
+                for (k, i) in members.iter().enumerate() {
+                    for j in &members[k + 1..] {
+                        let (box_i, box_j) = (boxes[*i].unwrap(), boxes[*j].unwrap());
+                        if !overlap(box_i, box_j) {
+                            continue;
+                        }
+
+                        //Only the cell holding the lowest corner of the overlap reports the pair
+                        if cell(box_i.0.max(box_j.0)) != *key {
+                            continue;
+                        }
+
+                        found.extend(close_pair((*i).min(*j), (*i).max(*j)));
+                    }
+                }
+                found
+            })
+            .collect();
+
+        //Oversized paths against every star, a pair of two oversized paths is reported by the lower index
+        encounters.par_extend(
+            (0..states.len())
+                .into_par_iter()
+                .filter(|i| oversized[*i])
+                .flat_map_iter(|i| {
+                    let box_i = boxes[i].unwrap();
+                    let boxes = &boxes;
+                    let oversized = &oversized;
+                    (0..states.len()).filter_map(move |j| {
+                        let box_j = boxes[j]?;
+                        if j == i || (oversized[j] && j < i) || !overlap(box_i, box_j) {
+                            return None;
+                        }
+
+                        close_pair(i.min(j), i.max(j))
+                    })
+                }),
+        );
+
+        encounters.par_sort_unstable_by_key(|(i, j, _)| (*i, *j));
+
+        encounters
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use rand::{Rng, SeedableRng};
+        use rand_chacha::ChaCha8Rng;
+
+        //Star moving past the sun along x with an impact parameter of 1e15 m along y
+        fn passing_star() -> (DVec3, DVec3) {
+            (DVec3::new(1e16, 1e15, 0.), DVec3::new(-3e4, 0., 0.))
+        }
+
+        #[test]
+        fn linear_encounter_of_a_passing_star() {
+            let encounter = linear_encounter((DVec3::ZERO, DVec3::ZERO), passing_star());
+
+            assert!((encounter.time - (1e16 / 3e4 / JULIAN_YEAR)).abs() < 1e-9);
+            assert!((encounter.distance - 1e15).abs() < 1e-3);
+            assert_eq!(encounter.relative_velocity, 3e4);
+        }
+
+        #[test]
+        fn linear_encounter_is_symmetric() {
+            let star_a = (DVec3::new(-2e15, 3e15, 1e15), DVec3::new(1e4, -5e3, 2e3));
+            let ab = linear_encounter(star_a, passing_star());
+            let ba = linear_encounter(passing_star(), star_a);
+
+            assert!((ab.time - ba.time).abs() < 1e-9);
+            assert!((ab.distance - ba.distance).abs() < 1e-3);
+        }
+
+        #[test]
+        fn galactic_encounter_matches_the_linear_encounter_over_short_times() {
+            let sun = (DVec3::ZERO, DVec3::ZERO);
+            let linear = linear_encounter(sun, passing_star());
+            let galactic = galactic_encounter(
+                sun,
+                passing_star(),
+                &MilkyWayPotential::default(),
+                &GalactocentricFrame::default(),
+                2e4,
+                1000,
+            )
+            .unwrap();
+
+            assert!((galactic.time - linear.time).abs() < 1.);
+            assert!((galactic.distance / linear.distance - 1.).abs() < 1e-4);
+            assert!((galactic.relative_velocity / linear.relative_velocity - 1.).abs() < 1e-4);
+        }
+
+        #[test]
+        fn galactic_encounter_rejects_empty_searches() {
+            let sun = (DVec3::ZERO, DVec3::ZERO);
+            let potential = MilkyWayPotential::default();
+            let frame = GalactocentricFrame::default();
+
+            assert_eq!(
+                galactic_encounter(sun, passing_star(), &potential, &frame, 2e4, 0),
+                None
+            );
+            assert_eq!(
+                galactic_encounter(sun, passing_star(), &potential, &frame, f64::NAN, 100),
+                None
+            );
+            assert_eq!(
+                galactic_encounter(sun, passing_star(), &potential, &frame, f64::INFINITY, 100),
+                None
+            );
+        }
+
+        #[test]
+        fn linear_encounter_within_a_past_only_window() {
+            //The passing star is closest in the future, searching only the past stops at the epoch
+            let sun = (DVec3::ZERO, DVec3::ZERO);
+            let past = linear_encounter_within(sun, passing_star(), 0., -5e6);
+            assert_eq!(past.time, 0.);
+            assert_eq!(past.distance, passing_star().0.length());
+
+            //A receding star was closest in the past, the window order does not matter
+            let receding = (passing_star().0, -passing_star().1);
+            let forward = linear_encounter_within(sun, receding, -5e6, 0.);
+            let backward = linear_encounter_within(sun, receding, 0., -5e6);
+            assert_eq!(forward, backward);
+            assert!((forward.distance - 1e15).abs() < 1e-3);
+            assert!(forward.time < 0.);
+        }
+
+        //States of stars spread over a cube with a side of 20 pc moving at up to 50 km/s
+        fn random_states(count: usize) -> std::vec::Vec<(DVec3, DVec3)> {
+            let mut rng = ChaCha8Rng::seed_from_u64(7);
+            (0..count)
+                .map(|_| {
+                    let position = DVec3::new(rng.gen(), rng.gen(), rng.gen()) - 0.5;
+                    let velocity = DVec3::new(rng.gen(), rng.gen(), rng.gen()) - 0.5;
+                    (position * 6.2e17, velocity * 1e5)
+                })
+                .collect()
+        }
+
+        fn brute_force(
+            states: &[(DVec3, DVec3)],
+            max_distance: f64,
+            start: f64,
+            end: f64,
+        ) -> std::vec::Vec<(usize, usize, Encounter)> {
+            let mut expected = std::vec::Vec::new();
+            for i in 0..states.len() {
+                for j in i + 1..states.len() {
+                    let encounter = linear_encounter_within(states[i], states[j], start, end);
+                    if encounter.distance <= max_distance {
+                        expected.push((i, j, encounter));
+                    }
+                }
+            }
+
+            expected
+        }
+
+        #[test]
+        fn catalog_encounters_match_brute_force() {
+            let states = random_states(300);
+            let max_distance = 1e16;
+
+            for (start, end) in [(0., 1e5), (0., -1e5), (-2e4, 3e4)] {
+                let expected = brute_force(&states, max_distance, start, end);
+
+                assert!(!expected.is_empty());
+                assert_eq!(
+                    catalog_encounters(&states, max_distance, start, end),
+                    expected
+                );
+            }
+        }
+
+        #[test]
+        fn catalog_encounters_with_outliers() {
+            //Two stars at 1e9 m/s per axis crossing star 10 at the epoch, as tiny parallaxes give, and one from a negative parallax
+            let mut states = random_states(300);
+            states.push((states[10].0, DVec3::splat(1e9)));
+            states.push((states[10].0, DVec3::splat(-1e9)));
+            states.push((DVec3::splat(f64::INFINITY), DVec3::splat(f64::NAN)));
+            let max_distance = 3e16;
+
+            for (start, end) in [(0., 1e5), (-1e5, 1e5)] {
+                let expected = brute_force(&states, max_distance, start, end);
+                let found = catalog_encounters(&states, max_distance, start, end);
+
+                assert!(found.iter().any(|(i, j, _)| (*i, *j) == (10, 300)));
+                assert!(found.iter().any(|(i, j, _)| (*i, *j) == (300, 301)));
+                assert!(found.iter().all(|(_, j, _)| *j != 302));
+                assert_eq!(found, expected);
+            }
+
+            //Nothing but non-finite states
+            let broken = [(DVec3::splat(f64::NAN), DVec3::ZERO); 3];
+            assert!(catalog_encounters(&broken, max_distance, 0., 1e5).is_empty());
+        }
+
+        #[test]
+        fn sun_encounters_match_brute_force() {
+            let states = random_states(300);
+            let sun = (DVec3::ZERO, DVec3::ZERO);
+
+            let expected: std::vec::Vec<(usize, Encounter)> = states
+                .iter()
+                .enumerate()
+                .map(|(index, state)| (index, linear_encounter_within(sun, *state, 0., -1e5)))
+                .filter(|(_, encounter)| encounter.distance <= 1e17)
+                .collect();
+
+            assert!(!expected.is_empty());
+            assert_eq!(sun_encounters(&states, 1e17, 0., -1e5), expected);
+        }
     }
 }
 
-/// Set of common functions used by `spv-rs` exposed if you want to used them for your own calculations.
-pub mod common {
+/// Orbital elements of a companion relative to its primary and the [`Orbit`] they describe, so that the seven positional arguments of
+/// [`super::position::companion_relative_position`] and friends cannot be swapped by accident. The free functions are thin wrappers around [`Orbit`].
+/// Units follow the free functions: a in au (negative for hyperbolic orbits and the periastron distance for parabolic orbits), period in years
+/// (the time scale 2 * pi * sqrt(|a|^3 / mu) for unbound orbits), angles in degrees and times since periastron in years.
+pub mod orbit {
+    use super::common::{
+        a_to_au, au_to_m, is_parabolic, kepler_solution, semi_parameter, signed_semi_major_axis,
+        standard_gravitational_parameter, true_anomaly, KeplerSolution,
+    };
     use super::coordinate_transforms::euler_angle_transformations;
-    use super::position::companion_relative_position;
-    use super::velocity::companion_relative_velocity;
-    use glam::f64::DVec3;
+    use super::galactic_potential::GM_SUN;
+    use super::time::JULIAN_YEAR;
+    use glam::f64::{DMat3, DVec2, DVec3};
+    use serde::{Deserialize, Serialize};
+    use std::fmt;
+
+    /// Reason why a set of orbital elements was rejected.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum OrbitalElementsError {
+        /// The semi major-axis is zero or not finite.
+        SemiMajorAxis,
+        /// The eccentricity is negative or not finite.
+        Eccentricity,
+        /// The period is zero, negative or not finite.
+        Period,
+        /// One of the angles is not finite.
+        Angle,
+    }
 
-    /// Takes a in as (arcseconds) and parllax in mas (milliarcsecond) and outputs a in au.
-    pub fn a_to_au(parallax: f64, a: f64) -> f64 {
-        a * parallax_to_parsec(parallax)
+    impl fmt::Display for OrbitalElementsError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Self::SemiMajorAxis => write!(f, "semi major-axis must be finite and non-zero"),
+                Self::Eccentricity => write!(f, "eccentricity must be finite and non-negative"),
+                Self::Period => write!(f, "period must be finite and positive"),
+                Self::Angle => write!(f, "lotn, aop and i must be finite"),
+            }
+        }
+    }
+
+    impl std::error::Error for OrbitalElementsError {}
+
+    /// Keplerian elements of a companion relative to its primary, a is semi major-axis in au, e is eccentricity, period is in years,
+    /// lotn is Longitude of the node (Omega) in degrees, aop is Argument of periastron (omega) in degrees and i is the Inclination in degrees.
+    /// The fields are private so that elements can only be created through the validating constructors, deserializing validates them too.
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    #[serde(try_from = "OrbitalElementsFields")]
+    pub struct OrbitalElements {
+        a: f64,
+        e: f64,
+        period: f64,
+        lotn: f64,
+        aop: f64,
+        i: f64,
+    }
+
+    //Unvalidated fields that serde deserializes before going through OrbitalElements::new
+    #[derive(Deserialize)]
+    struct OrbitalElementsFields {
+        a: f64,
+        e: f64,
+        period: f64,
+        lotn: f64,
+        aop: f64,
+        i: f64,
     }
 
-    /// Calculates total declination in degrees with declination_degree, declination_min and declination_s in degrees, minutes and seconds respectively.
-    pub fn declination_total(
-        declination_degree: f64,
-        declination_min: f64,
-        declination_s: f64,
-    ) -> f64 {
-        declination_degree + (declination_min / 60.) + (declination_s / 3600.)
-    }
+    impl TryFrom<OrbitalElementsFields> for OrbitalElements {
+        type Error = OrbitalElementsError;
+
+        fn try_from(fields: OrbitalElementsFields) -> Result<Self, Self::Error> {
+            Self::new(
+                fields.a,
+                fields.e,
+                fields.period,
+                fields.lotn,
+                fields.aop,
+                fields.i,
+            )
+        }
+    }
+
+    impl OrbitalElements {
+        /// Creates and validates a set of elements, see [`OrbitalElements`] for the units.
+        pub fn new(
+            a: f64,
+            e: f64,
+            period: f64,
+            lotn: f64,
+            aop: f64,
+            i: f64,
+        ) -> Result<Self, OrbitalElementsError> {
+            Self {
+                a,
+                e,
+                period,
+                lotn,
+                aop,
+                i,
+            }
+            .validate()
+        }
+
+        /// Elements from the standard gravitational parameter mu in m^3/s^2 instead of the period.
+        pub fn from_mu(
+            a: f64,
+            e: f64,
+            mu: f64,
+            lotn: f64,
+            aop: f64,
+            i: f64,
+        ) -> Result<Self, OrbitalElementsError> {
+            let period =
+                2. * std::f64::consts::PI * (au_to_m(a.abs()).powf(3.) / mu).sqrt() / JULIAN_YEAR;
+
+            Self::new(a, e, period, lotn, aop, i)
+        }
+
+        /// Elements from the masses of the primary and the companion in solar masses instead of the period.
+        pub fn from_masses(
+            a: f64,
+            e: f64,
+            mass_primary: f64,
+            mass_companion: f64,
+            lotn: f64,
+            aop: f64,
+            i: f64,
+        ) -> Result<Self, OrbitalElementsError> {
+            Self::from_mu(a, e, GM_SUN * (mass_primary + mass_companion), lotn, aop, i)
+        }
+
+        /// Elements of a visual binary where a is the angular semi major-axis in arcseconds and parallax is in mas (milliarcseconds).
+        pub fn from_angular(
+            a: f64,
+            parallax: f64,
+            e: f64,
+            period: f64,
+            lotn: f64,
+            aop: f64,
+            i: f64,
+        ) -> Result<Self, OrbitalElementsError> {
+            Self::new(a_to_au(parallax, a), e, period, lotn, aop, i)
+        }
+
+        /// Elements from the periastron distance q in au and mu in m^3/s^2, which also works for parabolic orbits (e = 1).
+        pub fn from_periastron(
+            q: f64,
+            e: f64,
+            mu: f64,
+            lotn: f64,
+            aop: f64,
+            i: f64,
+        ) -> Result<Self, OrbitalElementsError> {
+            let a = if is_parabolic(e) { q } else { q / (1. - e) };
+
+            Self::from_mu(a, e, mu, lotn, aop, i)
+        }
+
+        //Elements without validation for the free functions of position and velocity, which have always accepted any input
+        pub(crate) fn unchecked(a: f64, e: f64, period: f64, lotn: f64, aop: f64, i: f64) -> Self {
+            Self {
+                a,
+                e,
+                period,
+                lotn,
+                aop,
+                i,
+            }
+        }
+
+        //Checks that the elements describe an orbit and returns them with the sign of a following signed_semi_major_axis
+        fn validate(self) -> Result<Self, OrbitalElementsError> {
+            if !self.a.is_finite() || self.a == 0. {
+                return Err(OrbitalElementsError::SemiMajorAxis);
+            }
+            if !self.e.is_finite() || self.e < 0. {
+                return Err(OrbitalElementsError::Eccentricity);
+            }
+            if !self.period.is_finite() || self.period <= 0. {
+                return Err(OrbitalElementsError::Period);
+            }
+            if !(self.lotn.is_finite() && self.aop.is_finite() && self.i.is_finite()) {
+                return Err(OrbitalElementsError::Angle);
+            }
+
+            Ok(Self {
+                a: signed_semi_major_axis(self.a, self.e),
+                ..self
+            })
+        }
+
+        /// Same elements with another semi major-axis a (e.g. converted from arcseconds to au), validated again.
+        pub fn with_a(self, a: f64) -> Result<Self, OrbitalElementsError> {
+            Self { a, ..self }.validate()
+        }
+
+        /// Semi major-axis in au, negative for hyperbolic orbits and the periastron distance for parabolic orbits.
+        pub fn a(&self) -> f64 {
+            self.a
+        }
+
+        /// Eccentricity.
+        pub fn e(&self) -> f64 {
+            self.e
+        }
+
+        /// Period in years, the time scale 2 * pi * sqrt(|a|^3 / mu) for unbound orbits.
+        pub fn period(&self) -> f64 {
+            self.period
+        }
+
+        /// Longitude of the node (Omega) in degrees.
+        pub fn lotn(&self) -> f64 {
+            self.lotn
+        }
+
+        /// Argument of periastron (omega) in degrees.
+        pub fn aop(&self) -> f64 {
+            self.aop
+        }
+
+        /// Inclination in degrees.
+        pub fn i(&self) -> f64 {
+            self.i
+        }
+
+        /// True for hyperbolic and parabolic orbits (e >= 1, see [`is_parabolic`]).
+        pub fn is_unbound(&self) -> bool {
+            self.e >= 1. || is_parabolic(self.e)
+        }
+    }
+
+    /// Orbit described by a set of [`OrbitalElements`], with the rotation to the reference frame and the constants of the motion computed once.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Orbit {
+        elements: OrbitalElements,
+        rotation: DMat3,
+        mu: f64,
+        semi_parameter: f64,
+    }
+
+    impl Orbit {
+        /// Creates the orbit described by elements, see [`OrbitalElements::new`] to validate them first.
+        pub fn new(elements: OrbitalElements) -> Self {
+            Self {
+                elements,
+                rotation: euler_angle_transformations(elements.lotn, elements.aop, elements.i),
+                mu: standard_gravitational_parameter(elements.a, elements.period),
+                semi_parameter: semi_parameter(elements.a, elements.e),
+            }
+        }
+
+        /// Elements that describe the orbit.
+        pub fn elements(&self) -> OrbitalElements {
+            self.elements
+        }
+
+        /// Standard gravitational parameter of the system in m^3/s^2.
+        pub fn mu(&self) -> f64 {
+            self.mu
+        }
+
+        /// True anomaly in radians at t_p years since periastron.
+        pub fn true_anomaly_at(&self, t_p: f64) -> f64 {
+            true_anomaly(self.elements.e, self.elements.period, t_p)
+        }
+
+        /// Solution of Kepler's equation at t_p years since periastron, see [`kepler_solution`].
+        /// Every position and velocity of the orbit at t_p is computed from it, so converged tells whether they can be trusted.
+        pub fn kepler_solution_at(&self, t_p: f64) -> KeplerSolution {
+            kepler_solution(self.elements.e, self.elements.period, t_p)
+        }
+
+        /// Position in the orbital plane in meters at t_p years since periastron, x towards periastron.
+        pub fn orbital_plane_position_at(&self, t_p: f64) -> DVec2 {
+            let v = self.true_anomaly_at(t_p);
+            let r = self.semi_parameter / (1. + (self.elements.e * v.cos()));
+
+            DVec2::new(r * v.cos(), r * v.sin())
+        }
+
+        /// Velocity in the orbital plane in meters/second at t_p years since periastron, x towards periastron.
+        pub fn orbital_plane_velocity_at(&self, t_p: f64) -> DVec2 {
+            let v = self.true_anomaly_at(t_p);
+            let speed = (self.mu / self.semi_parameter).sqrt();
+
+            DVec2::new(-speed * v.sin(), speed * (self.elements.e + v.cos()))
+        }
+
+        /// Position in meters relative to the primary in the reference frame at t_p years since periastron.
+        pub fn position_at(&self, t_p: f64) -> DVec3 {
+            let plane = self.orbital_plane_position_at(t_p);
+
+            (self.rotation.x_axis * plane.x) + (self.rotation.y_axis * plane.y)
+        }
+
+        /// Velocity in meters/second relative to the primary in the reference frame at t_p years since periastron.
+        pub fn velocity_at(&self, t_p: f64) -> DVec3 {
+            let plane = self.orbital_plane_velocity_at(t_p);
+
+            (self.rotation.x_axis * plane.x) + (self.rotation.y_axis * plane.y)
+        }
+
+        /// Position of periastron in meters relative to the primary in the reference frame.
+        pub fn perigee(&self) -> DVec3 {
+            self.rotation.x_axis * (self.semi_parameter / (1. + self.elements.e))
+        }
+
+        /// Position of apastron in meters relative to the primary in the reference frame, None for unbound orbits.
+        pub fn apogee(&self) -> Option<DVec3> {
+            if self.elements.is_unbound() {
+                return None;
+            }
+
+            Some(-self.rotation.x_axis * (self.semi_parameter / (1. - self.elements.e)))
+        }
+    }
+
+    //Relative tolerance below which an orbit is treated as circular or equatorial
+    const SINGULAR_TOLERANCE: f64 = 1e-10;
+
+    /// Keplerian elements of a companion from its position in meters and velocity in meters/second relative to the primary,
+    /// the inverse of [`Orbit::position_at`] and [`Orbit::velocity_at`]. mu is the standard gravitational parameter in m^3/s^2.
+    /// Output is the elements together with the time since periastron in years (negative before periastron on unbound orbits).
+    ///
+    /// The singular cases use the following conventions:
+    /// for equatorial orbits (i = 0 or 180) the node is undefined and lotn is 0, so aop is the longitude of periastron,
+    /// for circular orbits periastron is undefined and aop is 0, so the time since periastron counts from the ascending node
+    /// (or from the x-axis if the orbit is also equatorial), and orbits with e within [`super::common::PARABOLIC_TOLERANCE`] of 1 are parabolic with a the periastron distance.
+    /// Radial orbits (velocity along the position or zero, so no angular momentum) have no orbital plane and give `None`.
+    pub fn elements_from_state(
+        position: DVec3,
+        velocity: DVec3,
+        mu: f64,
+    ) -> Option<(OrbitalElements, f64)> {
+        let r = position.length();
+        let angular_momentum = position.cross(velocity);
+        let h = angular_momentum.length();
+        if h <= SINGULAR_TOLERANCE * r * velocity.length() || !h.is_finite() {
+            return None;
+        }
+        let h_unit = angular_momentum / h;
+
+        let eccentricity_vector = ((position * (velocity.length_squared() - (mu / r)))
+            - (velocity * position.dot(velocity)))
+            / mu;
+        let mut e = eccentricity_vector.length();
+        let parabolic = is_parabolic(e);
+        let circular = e < SINGULAR_TOLERANCE;
+        if parabolic {
+            e = 1.;
+        } else if circular {
+            e = 0.;
+        }
+
+        let i = h_unit.z.clamp(-1., 1.).acos().to_degrees();
+
+        //Ascending node, along the x-axis by convention for equatorial orbits
+        let node = DVec3::Z.cross(angular_momentum);
+        let equatorial = node.length() < SINGULAR_TOLERANCE * h;
+        let (node_unit, lotn) = if equatorial {
+            (DVec3::X, 0.)
+        } else {
+            (
+                node.normalize(),
+                node.y.atan2(node.x).to_degrees().rem_euclid(360.),
+            )
+        };
+
+        //Periastron direction, the node for circular orbits
+        let periastron = if circular {
+            node_unit
+        } else {
+            eccentricity_vector.normalize()
+        };
+        let aop = node_unit
+            .cross(periastron)
+            .dot(h_unit)
+            .atan2(node_unit.dot(periastron))
+            .to_degrees()
+            .rem_euclid(360.);
+        let v = periastron
+            .cross(position)
+            .dot(h_unit)
+            .atan2(periastron.dot(position));
+
+        //Semi major-axis in meters, the periastron distance for parabolic orbits
+        let a_si = if parabolic {
+            h.powf(2.) / (2. * mu)
+        } else {
+            -mu / (2. * ((velocity.length_squared() / 2.) - (mu / r)))
+        };
+        let mean_motion = (mu / a_si.abs().powf(3.)).sqrt();
+
+        let t_p_si = if parabolic {
+            let d = (v / 2.).tan();
+            2_f64.sqrt() * (d + (d.powf(3.) / 3.)) / mean_motion
+        } else if e > 1. {
+            let hyp_anom = 2. * (((e - 1.) / (e + 1.)).sqrt() * (v / 2.).tan()).atanh();
+            ((e * hyp_anom.sinh()) - hyp_anom) / mean_motion
+        } else {
+            let ecc_anom =
+                2. * ((1. - e).sqrt() * (v / 2.).sin()).atan2((1. + e).sqrt() * (v / 2.).cos());
+            ((ecc_anom - (e * ecc_anom.sin())).rem_euclid(2. * std::f64::consts::PI)) / mean_motion
+        };
+
+        let elements = OrbitalElements {
+            a: a_si / au_to_m(1.),
+            e,
+            period: 2. * std::f64::consts::PI / mean_motion / JULIAN_YEAR,
+            lotn,
+            aop,
+            i,
+        };
+
+        Some((elements, t_p_si / JULIAN_YEAR))
+    }
+
+    /// Same as [`elements_from_state`] with the masses of the primary and the companion in solar masses instead of mu.
+    pub fn elements_from_state_masses(
+        position: DVec3,
+        velocity: DVec3,
+        mass_primary: f64,
+        mass_companion: f64,
+    ) -> Option<(OrbitalElements, f64)> {
+        elements_from_state(position, velocity, GM_SUN * (mass_primary + mass_companion))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::position::companion_relative_position;
+        use crate::velocity::{companion_relative_velocity, companion_velocity_value};
+
+        //Elliptic, parabolic and hyperbolic orbits around the sun with periastron at 1 au, tilted out of the reference plane
+        fn orbits() -> [Orbit; 3] {
+            [0.5, 1., 1.5].map(|e| {
+                Orbit::new(OrbitalElements::from_periastron(1., e, GM_SUN, 40., 70., 30.).unwrap())
+            })
+        }
+
+        #[test]
+        fn periastron_distance_at_t_p_0() {
+            for orbit in orbits() {
+                assert!((orbit.position_at(0.).length() / au_to_m(1.) - 1.).abs() < 1e-12);
+                assert!((orbit.perigee() - orbit.position_at(0.)).length() < 1e-3);
+            }
+        }
+
+        #[test]
+        fn vis_viva_holds_for_every_eccentricity() {
+            for orbit in orbits() {
+                let elements = orbit.elements();
+                for t_p in [-3., -0.2, 0., 0.1, 2.] {
+                    let r = orbit.position_at(t_p).length();
+                    let speed_sq = orbit.velocity_at(t_p).length_squared();
+
+                    //Zero energy for the parabola
+                    let inverse_a = if is_parabolic(elements.e) {
+                        0.
+                    } else {
+                        1. / au_to_m(elements.a)
+                    };
+                    let expected = orbit.mu() * ((2. / r) - inverse_a);
+
+                    assert!((speed_sq / expected - 1.).abs() < 1e-12);
+                }
+            }
+        }
+
+        #[test]
+        fn velocity_is_the_derivative_of_the_position() {
+            let dt = 1e-6;
+            for orbit in orbits() {
+                for t_p in [-1., 0., 0.3, 5.] {
+                    let derivative = (orbit.position_at(t_p + dt) - orbit.position_at(t_p - dt))
+                        / (2. * dt * JULIAN_YEAR);
+                    let velocity = orbit.velocity_at(t_p);
+
+                    assert!((derivative - velocity).length() / velocity.length() < 1e-8);
+                }
+            }
+        }
+
+        #[test]
+        fn angular_momentum_is_conserved() {
+            for orbit in orbits() {
+                let h = orbit.position_at(0.).cross(orbit.velocity_at(0.));
+                let p = semi_parameter(orbit.elements().a, orbit.elements().e);
+                assert!((h.length() / (orbit.mu() * p).sqrt() - 1.).abs() < 1e-12);
+
+                for t_p in [-2., 0.7, 10.] {
+                    let other = orbit.position_at(t_p).cross(orbit.velocity_at(t_p));
+                    assert!((other - h).length() / h.length() < 1e-12);
+                }
+            }
+        }
+
+        #[test]
+        fn hyperbolic_speed_tends_to_the_excess_velocity() {
+            //Hyperbolic excess velocity sqrt(-mu / a), the speed at infinity
+            let orbit = orbits()[2];
+            let excess = (-orbit.mu() / au_to_m(orbit.elements().a)).sqrt();
+
+            assert!(orbit.elements().a < 0. && orbit.elements().is_unbound());
+            assert_eq!(orbit.apogee(), None);
+            assert!((orbit.velocity_at(1e6).length() / excess - 1.).abs() < 1e-4);
+            assert!(orbit.velocity_at(1e6).length() > excess);
+
+            //The parabola escapes with zero speed at infinity
+            let parabola = orbits()[1];
+            assert!(parabola.velocity_at(1e6).length() < 1e-2 * parabola.velocity_at(0.).length());
+        }
+
+        #[test]
+        fn free_functions_agree_with_orbit() {
+            for orbit in orbits() {
+                let OrbitalElements {
+                    a,
+                    e,
+                    period,
+                    lotn,
+                    aop,
+                    i,
+                } = orbit.elements();
+
+                for t_p in [-0.5, 0., 1.5] {
+                    let position = companion_relative_position(a, e, period, t_p, lotn, aop, i);
+                    let velocity = companion_relative_velocity(a, e, period, t_p, lotn, aop, i);
+
+                    assert_eq!(position, orbit.position_at(t_p));
+                    assert_eq!(velocity, orbit.velocity_at(t_p));
+                    assert!(
+                        (companion_velocity_value(a, e, period, t_p) / velocity.length() - 1.)
+                            .abs()
+                            < 1e-12
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn orbit_keeps_its_elements() {
+            let elements = OrbitalElements::new(-2., 1.5, 3., 10., 20., 30.).unwrap();
+
+            assert_eq!(Orbit::new(elements).elements(), elements);
+            assert_eq!(elements.a, -2.);
+        }
+
+        #[test]
+        fn invalid_elements_are_rejected() {
+            assert_eq!(
+                OrbitalElements::new(0., 0.5, 1., 0., 0., 0.),
+                Err(OrbitalElementsError::SemiMajorAxis)
+            );
+            assert_eq!(
+                OrbitalElements::new(1., -0.1, 1., 0., 0., 0.),
+                Err(OrbitalElementsError::Eccentricity)
+            );
+            assert_eq!(
+                OrbitalElements::new(1., 0.5, 0., 0., 0., 0.),
+                Err(OrbitalElementsError::Period)
+            );
+            assert_eq!(
+                OrbitalElements::new(1., 0.5, 1., f64::NAN, 0., 0.),
+                Err(OrbitalElementsError::Angle)
+            );
+
+            //A hyperbolic orbit given with a positive a gets the negative sign
+            assert_eq!(
+                OrbitalElements::new(2., 1.5, 3., 0., 0., 0.).unwrap().a,
+                -2.
+            );
+        }
+
+        #[test]
+        fn from_masses_gives_the_period_of_the_earth() {
+            let elements =
+                OrbitalElements::from_masses(1., 0.0167, 1., 3.0e-6, 0., 0., 0.).unwrap();
+
+            assert!((elements.period - 1.).abs() < 1e-4);
+        }
+
+        //Elements and time since periastron recovered from the state of orbit at t_p
+        fn round_trip(elements: OrbitalElements, t_p: f64) -> (OrbitalElements, f64) {
+            let orbit = Orbit::new(elements);
+
+            elements_from_state(orbit.position_at(t_p), orbit.velocity_at(t_p), orbit.mu()).unwrap()
+        }
+
+        fn assert_elements(found: OrbitalElements, expected: OrbitalElements) {
+            let angle = |a: f64, b: f64| (a - b + 180.).rem_euclid(360.) - 180.;
+
+            assert!((found.a / expected.a - 1.).abs() < 1e-9);
+            assert!((found.e - expected.e).abs() < 1e-9);
+            assert!((found.period / expected.period - 1.).abs() < 1e-9);
+            assert!(angle(found.lotn, expected.lotn).abs() < 1e-7);
+            assert!(angle(found.aop, expected.aop).abs() < 1e-7);
+            assert!((found.i - expected.i).abs() < 1e-7);
+        }
+
+        #[test]
+        fn elements_from_state_of_elliptic_orbits() {
+            let elements = OrbitalElements::from_mu(2., 0.6, GM_SUN, 40., 70., 30.).unwrap();
+            let (found, t_p) = round_trip(elements, 0.7);
+            assert_elements(found, elements);
+            assert!((t_p - 0.7).abs() < 1e-9);
+
+            //Before periastron the time is counted from the previous periastron
+            let (_, t_p) = round_trip(elements, -0.7);
+            assert!((t_p - (elements.period - 0.7)).abs() < 1e-9);
+
+            //Equatorial, the node is along the x-axis and aop is the longitude of periastron
+            let equatorial = OrbitalElements::from_mu(2., 0.6, GM_SUN, 40., 70., 0.).unwrap();
+            let (found, _) = round_trip(equatorial, 0.7);
+            assert_elements(
+                found,
+                OrbitalElements {
+                    lotn: 0.,
+                    aop: 110.,
+                    ..equatorial
+                },
+            );
+        }
+
+        #[test]
+        fn elements_from_state_of_circular_orbits() {
+            //Periastron at the ascending node, the time counts from the node
+            let elements = OrbitalElements::from_mu(2., 0., GM_SUN, 40., 0., 30.).unwrap();
+            let (found, t_p) = round_trip(elements, 0.7);
+            assert_elements(found, elements);
+            assert_eq!(found.e, 0.);
+            assert!((t_p - 0.7).abs() < 1e-9);
+        }
+
+        #[test]
+        fn elements_from_state_of_parabolic_orbits() {
+            let elements = OrbitalElements::from_periastron(1., 1., GM_SUN, 40., 70., 30.).unwrap();
+            for t_p in [-0.2, 0.5] {
+                let (found, found_t_p) = round_trip(elements, t_p);
+                assert_elements(found, elements);
+                assert_eq!(found.e, 1.);
+                assert!((found_t_p - t_p).abs() < 1e-9);
+            }
+        }
+
+        #[test]
+        fn elements_from_state_of_hyperbolic_orbits() {
+            let elements = OrbitalElements::from_mu(-2., 1.5, GM_SUN, 40., 70., 150.).unwrap();
+            for t_p in [-0.4, 0., 3.] {
+                let (found, found_t_p) = round_trip(elements, t_p);
+                assert_elements(found, elements);
+                assert!((found_t_p - t_p).abs() < 1e-9);
+            }
+        }
+
+        #[test]
+        fn radial_orbits_have_no_elements() {
+            let position = DVec3::new(1e11, 2e11, 0.);
+
+            assert_eq!(elements_from_state(position, position * 1e-7, GM_SUN), None);
+            assert_eq!(elements_from_state(position, DVec3::ZERO, GM_SUN), None);
+            assert_eq!(elements_from_state(DVec3::ZERO, DVec3::X, GM_SUN), None);
+            assert_eq!(
+                elements_from_state_masses(position, -position * 1e-7, 1., 0.),
+                None
+            );
+        }
+
+        #[test]
+        fn deserialized_elements_are_validated() {
+            let rows = "a,e,period,lotn,aop,i\n2,1.5,3,10,20,30\n1,-0.5,1,0,0,0\n";
+            let mut reader = csv::Reader::from_reader(rows.as_bytes());
+            let elements: std::vec::Vec<Result<OrbitalElements, csv::Error>> =
+                reader.deserialize().collect();
+
+            //Same sign convention for a as the constructors
+            assert_eq!(
+                *elements[0].as_ref().unwrap(),
+                OrbitalElements::new(-2., 1.5, 3., 10., 20., 30.).unwrap()
+            );
+            assert!(elements[1].is_err());
+        }
+
+        #[test]
+        fn with_a_validates_the_new_semi_major_axis() {
+            let elements = OrbitalElements::new(2., 0.5, 3., 10., 20., 30.).unwrap();
+            let scaled = elements.with_a(0.2).unwrap();
+
+            assert_eq!(scaled.a(), 0.2);
+            assert_eq!(
+                (
+                    scaled.e(),
+                    scaled.period(),
+                    scaled.lotn(),
+                    scaled.aop(),
+                    scaled.i()
+                ),
+                (0.5, 3., 10., 20., 30.)
+            );
+            assert_eq!(
+                elements.with_a(0.),
+                Err(OrbitalElementsError::SemiMajorAxis)
+            );
+            assert_eq!(
+                elements.with_a(f64::NAN),
+                Err(OrbitalElementsError::SemiMajorAxis)
+            );
+        }
+    }
+}
+
+/// Set of common functions used by `spv-rs` exposed if you want to used them for your own calculations.
+pub mod common {
+    use super::coordinate_transforms::euler_angle_transformations;
+    use super::position::companion_relative_position;
+    use super::time::JULIAN_YEAR;
+    use super::velocity::companion_relative_velocity;
+    use glam::f64::DVec3;
+
+    /// One parsec in meters, the value used by every distance in `spv-rs`.
+    pub const PARSEC: f64 = 3.0856778570831e16;
+
+    /// Takes a in as (arcseconds) and parllax in mas (milliarcsecond) and outputs a in au.
+    pub fn a_to_au(parallax: f64, a: f64) -> f64 {
+        a * parallax_to_parsec(parallax)
+    }
+
+    /// Calculates total declination in degrees with declination_degree, declination_min and declination_s in degrees, minutes and seconds respectively.
+    pub fn declination_total(
+        declination_degree: f64,
+        declination_min: f64,
+        declination_s: f64,
+    ) -> f64 {
+        declination_degree + (declination_min / 60.) + (declination_s / 3600.)
+    }
+
+    /// Calculates total right ascension in degrees with right_ascension_h, right_ascension_min and right_ascension_s in hours, minutes and seconds respectively.
+    pub fn right_ascension_total(
+        right_ascension_h: f64,
+        right_ascension_min: f64,
+        right_ascension_s: f64,
+    ) -> f64 {
+        (right_ascension_h * 15.)
+            + (right_ascension_min * (1. / 4.))
+            + (right_ascension_s * (1. / 240.))
+    }
+
+    /// Eccentricities within this distance of 1 are treated as parabolic by every function in `spv-rs`,
+    /// including the elements returned by [`super::orbit::elements_from_state`].
+    pub const PARABOLIC_TOLERANCE: f64 = 1e-10;
+
+    /// True if the eccentricity e is 1 within [`PARABOLIC_TOLERANCE`].
+    pub fn is_parabolic(e: f64) -> bool {
+        (e - 1.).abs() < PARABOLIC_TOLERANCE
+    }
+
+    /// Semi major-axis with the sign convention used for unbound orbits, negative for hyperbolic orbits (e > 1) and positive otherwise.
+    /// All functions that take both a and e accept either sign of a and go through this function.
+    /// For parabolic orbits (see [`is_parabolic`]) the semi major-axis is infinite and a is the periastron distance instead.
+    pub fn signed_semi_major_axis(a: f64, e: f64) -> f64 {
+        if e > 1. && !is_parabolic(e) {
+            -a.abs()
+        } else {
+            a.abs()
+        }
+    }
+
+    /// Calculates r min or the minimum distance between the primary and companion boides in a twobody system also known as perigee
+    /// (suffix may change depending on what object it reffers to).
+    /// Output is just the x coordinate in the ellipses plane in au.
+    pub fn perigee(a: f64, e: f64) -> f64 {
+        if is_parabolic(e) {
+            return a.abs();
+        }
+
+        signed_semi_major_axis(a, e) * (1. - e)
+    }
+
+    /// Calculates r max or the maximum distance between the primary and companion boides in a twobody system also known as apogee
+    /// (suffix may change depending on what object it reffers to).
+    /// Output is just the x coordinate in the ellipses plane in au, infinite for unbound orbits (e >= 1).
+    pub fn apogee(a: f64, e: f64) -> f64 {
+        if e >= 1. || is_parabolic(e) {
+            return f64::INFINITY;
+        }
+
+        a.abs() * (1. + e)
+    }
+
+    /// Calculates r min or the minimum distance between the primary and companion boides in a twobody system also known as perigee
+    /// (suffix may change depending on what object it reffers to).
+    /// Output is 3-dimensional vector that represents the coordinates for perigee rotated to be relative to the earth/sun plane in meters.
+    pub fn relative_perigee(a: f64, e: f64, lotn: f64, aop: f64, i: f64) -> DVec3 {
+        let x = au_to_m(perigee(a, e));
+
+        let euler_angle_transformations = euler_angle_transformations(lotn, aop, i).to_cols_array();
+        let x1 = euler_angle_transformations[0];
+        let x2 = euler_angle_transformations[1];
+        let x3 = euler_angle_transformations[2];
+
+        DVec3::new(x * x1, x * x2, x * x3)
+    }
+
+    /// Calculates r max or the maximum distance between the primary and companion boides in a twobody system also known as apogee
+    /// (suffix may change depending on what object it reffers to).
+    /// Output is 3-dimensional vector that represents the coordinates for apogee rotated to be relative to the earth/sun plane in meters.
+    pub fn relative_apogee(a: f64, e: f64, lotn: f64, aop: f64, i: f64) -> DVec3 {
+        let x = au_to_m(apogee(a, e));
+
+        let euler_angle_transformations = euler_angle_transformations(lotn, aop, i).to_cols_array();
+        let x1 = euler_angle_transformations[0];
+        let x2 = euler_angle_transformations[1];
+        let x3 = euler_angle_transformations[2];
+
+        DVec3::new(x * x1, x * x2, x * x3)
+    }
+
+    /// Default tolerance in radians for [`solve_kepler`] used by the functions in `spv-rs`.
+    pub const KEPLER_TOLERANCE: f64 = 1e-14;
+
+    /// Default maximum number of iterations for [`solve_kepler`] used by the functions in `spv-rs`.
+    pub const KEPLER_MAX_ITERATIONS: u32 = 50;
+
+    /// Solution of Kepler's equation, eccentric_anomaly is in radians, iterations is the number of iterations used
+    /// and converged is false if the tolerance was not reached within the maximum number of iterations.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct KeplerSolution {
+        pub eccentric_anomaly: f64,
+        pub iterations: u32,
+        pub converged: bool,
+    }
+
+    /// Solves Kepler's equation M = E - e * sin(E) for the eccentric anomaly E with Halley's method, mean_anomaly is in radians and e is eccentricity (0 <= e < 1).
+    /// The iteration stops when the correction is smaller than tolerance in radians or after max_iterations.
+    /// The starting guess of Danby (1987) keeps the iteration stable for eccentricities close to 1.
+    pub fn solve_kepler(
+        mean_anomaly: f64,
+        e: f64,
+        tolerance: f64,
+        max_iterations: u32,
+    ) -> KeplerSolution {
+        //Reduce the mean anomaly to -pi..pi and add the full turns back at the end
+        let turns = (mean_anomaly / (2. * std::f64::consts::PI)).round();
+        let m = mean_anomaly - (turns * 2. * std::f64::consts::PI);
+
+        let mut ecc_anom = m + (0.85 * e * m.sin().signum());
+        let mut iterations = 0;
+        let mut converged = false;
+        while iterations < max_iterations {
+            iterations += 1;
+
+            let f = ecc_anom - (e * ecc_anom.sin()) - m;
+            let f_prime = 1. - (e * ecc_anom.cos());
+            let f_double_prime = e * ecc_anom.sin();
+
+            let correction = f / (f_prime - (f * f_double_prime / (2. * f_prime)));
+            ecc_anom -= correction;
+
+            if correction.abs() <= tolerance {
+                converged = true;
+                break;
+            }
+        }
+
+        KeplerSolution {
+            eccentric_anomaly: ecc_anom + (turns * 2. * std::f64::consts::PI),
+            iterations,
+            converged,
+        }
+    }
+
+    /// Solves the hyperbolic Kepler equation M = e * sinh(H) - H for the hyperbolic anomaly H with Halley's method,
+    /// mean_anomaly is in radians and e is eccentricity (e > 1). The result is returned as a [`KeplerSolution`] with H in the eccentric_anomaly field.
+    pub fn solve_hyperbolic_kepler(
+        mean_anomaly: f64,
+        e: f64,
+        tolerance: f64,
+        max_iterations: u32,
+    ) -> KeplerSolution {
+        //Starting guess of Danby (1987)
+        let mut hyp_anom = mean_anomaly.signum() * ((2. * mean_anomaly.abs() / e) + 1.8).ln();
+        let mut iterations = 0;
+        let mut converged = false;
+        while iterations < max_iterations {
+            iterations += 1;
+
+            let f = (e * hyp_anom.sinh()) - hyp_anom - mean_anomaly;
+            let f_prime = (e * hyp_anom.cosh()) - 1.;
+            let f_double_prime = e * hyp_anom.sinh();
+
+            let correction = f / (f_prime - (f * f_double_prime / (2. * f_prime)));
+            hyp_anom -= correction;
+
+            if correction.abs() <= tolerance * hyp_anom.abs().max(1.) {
+                converged = true;
+                break;
+            }
+        }
+
+        KeplerSolution {
+            eccentric_anomaly: hyp_anom,
+            iterations,
+            converged,
+        }
+    }
+
+    /// Solves Barker's equation D + D^3 / 3 = M / sqrt(2) for parabolic orbits, where D = tan(true anomaly / 2).
+    /// mean_anomaly is in radians with the mean motion sqrt(mu / q^3) of the periastron distance q, which is what [`mean_anomaly`] gives when a is q.
+    pub fn solve_barker(mean_anomaly: f64) -> f64 {
+        let b = mean_anomaly / 2_f64.sqrt();
+        let w = ((1.5 * b) + ((2.25 * b.powf(2.)) + 1.).sqrt()).cbrt();
+
+        w - (1. / w)
+    }
+
+    /// Calculates the mean anomaly in radians with period and t_p (time since periastron) in years.
+    /// For unbound orbits (e >= 1) period is the time scale 2 * pi * sqrt(|a|^3 / mu), see [`standard_gravitational_parameter`].
+    pub fn mean_anomaly(period: f64, t_p: f64) -> f64 {
+        std::f64::consts::PI * 2. * t_p / period
+    }
+
+    /// Solves Kepler's equation for any eccentricity with period and t_p (time since periastron) in years, with [`solve_kepler`],
+    /// [`solve_hyperbolic_kepler`] or [`solve_barker`] and the default tolerance and maximum number of iterations.
+    /// The eccentric_anomaly field is what [`eccentric_anomaly`] returns, Barker's equation is solved in closed form and always converges.
+    pub fn kepler_solution(e: f64, period: f64, t_p: f64) -> KeplerSolution {
+        let mean_anom = mean_anomaly(period, t_p);
+
+        if is_parabolic(e) {
+            let parabolic_anomaly = solve_barker(mean_anom);
+
+            KeplerSolution {
+                eccentric_anomaly: parabolic_anomaly,
+                iterations: 0,
+                converged: parabolic_anomaly.is_finite(),
+            }
+        } else if e < 1. {
+            solve_kepler(mean_anom, e, KEPLER_TOLERANCE, KEPLER_MAX_ITERATIONS)
+        } else {
+            solve_hyperbolic_kepler(mean_anom, e, KEPLER_TOLERANCE, KEPLER_MAX_ITERATIONS)
+        }
+    }
+
+    /// Calculates the eccentric anomaly in radians with period and t_p (time since periastron) in years.
+    /// For hyperbolic orbits (e > 1) this is the hyperbolic anomaly H and for parabolic orbits (e = 1) the parabolic anomaly D = tan(true anomaly / 2).
+    /// Use [`kepler_solution`] to check that the solver converged.
+    pub fn eccentric_anomaly(e: f64, period: f64, t_p: f64) -> f64 {
+        kepler_solution(e, period, t_p).eccentric_anomaly
+    }
+
+    //True anomaly in radians from the anomaly of kepler_solution
+    fn true_anomaly_from(e: f64, ecc_anom: f64) -> f64 {
+        if is_parabolic(e) {
+            2. * ecc_anom.atan()
+        } else if e < 1. {
+            2. * (((1. + e).sqrt() * (ecc_anom * 0.5).sin())
+                .atan2((1. - e).sqrt() * (ecc_anom * 0.5).cos()))
+        } else {
+            2. * (((e + 1.) / (e - 1.)).sqrt() * (ecc_anom * 0.5).tanh()).atan()
+        }
+    }
+
+    /// Calculates the true anomaly in radians with period and t_p (time since periastron) in years, for any eccentricity.
+    /// If the solver did not converge this is computed from its last iterate, use [`true_anomaly_checked`] to catch that.
+    pub fn true_anomaly(e: f64, period: f64, t_p: f64) -> f64 {
+        true_anomaly_from(e, eccentric_anomaly(e, period, t_p))
+    }
+
+    /// Same as [`true_anomaly`] but `None` if the Kepler solver did not converge, e.g. for a non-finite t_p.
+    pub fn true_anomaly_checked(e: f64, period: f64, t_p: f64) -> Option<f64> {
+        let solution = kepler_solution(e, period, t_p);
+
+        solution
+            .converged
+            .then(|| true_anomaly_from(e, solution.eccentric_anomaly))
+    }
+
+    /// Calculates the flight path angle for the companion body in degrees, for any eccentricity.
+    pub fn flight_path_angle(e: f64, period: f64, t_p: f64) -> f64 {
+        let v = true_anomaly(e, period, t_p);
+
+        ((e * v.sin()) / (1. + (e * v.cos()))).atan().to_degrees()
+    }
+
+    /// Calculates the semi parameter for a twobody system and returns it in meters.
+    /// For parabolic orbits (e = 1) a is the periastron distance q in au and the semi parameter is 2 * q.
+    pub fn semi_parameter(a: f64, e: f64) -> f64 {
+        if is_parabolic(e) {
+            return 2. * au_to_m(a.abs());
+        }
+
+        au_to_m(signed_semi_major_axis(a, e)) * (1. - e.powf(2.))
+    }
+
+    /// Calculates the semi minor axis for a twobody system and returns it in meters.
+    /// For hyperbolic orbits (e > 1) this is the impact parameter |a| * sqrt(e^2 - 1) and for parabolic orbits it is infinite.
+    pub fn semi_minor_axis(a: f64, e: f64) -> f64 {
+        if is_parabolic(e) {
+            return f64::INFINITY;
+        }
+
+        au_to_m(a.abs()) * ((1. - e.powf(2.)).abs().sqrt())
+    }
+
+    /// Calculates the total radius for a twobody system
+    pub fn radius(a: f64, e: f64, period: f64, t_p: f64) -> f64 {
+        let nu = true_anomaly(e, period, t_p);
+        let p = semi_parameter(a, e);
+
+        p / (1. + (e * nu.cos()))
+    }
+
+    /// Calculates the specific angular momentum value
+    pub fn specific_angular_momentum_value(a: f64, e: f64, period: f64) -> f64 {
+        let p = semi_parameter(a, e);
+        let mu = standard_gravitational_parameter(a, period);
+
+        (mu * p).sqrt()
+    }
+
+    /// Calculates the specific angular momentum coordinates
+    pub fn specific_angular_momentum_coordinates(
+        a: f64,
+        e: f64,
+        period: f64,
+        t_p: f64,
+        lotn: f64,
+        aop: f64,
+        i: f64,
+    ) -> DVec3 {
+        let r = companion_relative_position(a, e, period, t_p, lotn, aop, i);
+        let v = companion_relative_velocity(a, e, period, t_p, lotn, aop, i);
+
+        DVec3::cross(r, v)
+    }
+
+    /// Calculates the stadard gravitational parameter.
+    /// For unbound orbits period is the time scale 2 * pi * sqrt(|a|^3 / mu) that takes the place of the period,
+    /// with a the periastron distance for parabolic orbits, so that the same relation holds.
+    pub fn standard_gravitational_parameter(a: f64, period: f64) -> f64 {
+        let period_si = period * JULIAN_YEAR;
+        let a_si = au_to_m(a.abs());
+
+        ((a_si.powf(3.)) * 4. * (std::f64::consts::PI.powf(2.))) / (period_si.powf(2.))
+    }
+
+    /// Specific mechanical energy (used by other equation but exposed here if you need it).
+    /// a has to be negative for hyperbolic orbits, see [`signed_semi_major_axis`], which makes the energy positive.
+    pub fn specific_mechanical_energy(a: f64, period: f64) -> f64 {
+        let a_si = au_to_m(a);
+        let mu = standard_gravitational_parameter(a, period);
+
+        0. - (mu / (2. * a_si))
+    }
+
+    /// If you dind't have the period already, for unbound orbits this is the time scale used in place of the period.
+    pub fn period(a: f64, mu: f64) -> f64 {
+        let a_si = au_to_m(a.abs());
+
+        2. * std::f64::consts::PI * (((a_si.powf(3.)) / mu).sqrt())
+    }
+
+    /// If you for some reason had these parameters and not a then here ya go, negative for hyperbolic orbits.
+    pub fn semi_major_axis(
+        standard_gravitational_parameter: f64,
+        specific_mechanical_energy: f64,
+    ) -> f64 {
+        0. - (standard_gravitational_parameter / (2. * specific_mechanical_energy))
+    }
+
+    /// Mean motion or n
+    pub fn mean_motion(a: f64, period: f64) -> f64 {
+        let a_si = au_to_m(a.abs());
+        let mu = standard_gravitational_parameter(a, period);
+
+        (mu / (a_si.powf(3.))).sqrt()
+    }
+
+    /// If you for some reason had these parameters and not e then here ya go.
+    pub fn eccentricity(
+        standard_gravitational_parameter: f64,
+        specific_mechanical_energy: f64,
+        specific_angular_momentum_value: f64,
+    ) -> f64 {
+        (1. + ((2. * specific_mechanical_energy * (specific_angular_momentum_value.powf(2.)))
+            / (standard_gravitational_parameter.powf(2.))))
+        .max(0.)
+        .sqrt()
+    }
+
+    /// Distance between one foci and the center of the ellipse in au.
+    pub fn linear_eccentricity(a: f64, e: f64) -> f64 {
+        a * e
+    }
+
+    /// Flattening is another way to defining eccentricity for an ellipse.
+    pub fn flattening(a: f64, e: f64) -> f64 {
+        let a_si = au_to_m(a);
+        let b = semi_minor_axis(a_si, e);
+
+        (a_si - b) / a_si
+    }
+
+    /// Parallax to parsec convert function where the parallax parameter is in mas (milliarcseconds).
+    pub fn parallax_to_parsec(parallax: f64) -> f64 {
+        1. / (parallax / 1000.)
+    }
+
+    /// Simple function motsly used by `spv-rs` to convert au to meters (used for semi major-axis in most cases but can be used for other stuff too).
+    pub fn au_to_m(a: f64) -> f64 {
+        a * 1000. * 149597870.7
+    }
+
+    /// Converts apparent magnitude to absolute magnitude where the parallax parameter is in as (arcseconds).
+    pub fn apparent_mag_to_absolute_mag(parallax: f64, apparent_magnitude: f64) -> f64 {
+        apparent_magnitude + 5. * (parallax.log10() + 1.)
+    }
+
+    /// Approximate effective temperature in kelvin from the B-V color index (Ballesteros' formula).
+    pub fn temperature(b_v_index: f64) -> f64 {
+        4600. * ((1. / ((0.92 * b_v_index) + 1.7)) + (1. / ((0.92 * b_v_index) + 0.62)))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        //Mean anomalies from -10 to 10 radians
+        fn mean_anomalies() -> impl Iterator<Item = f64> {
+            (-1000..=1000).map(|step| step as f64 * 0.01)
+        }
+
+        #[test]
+        fn solve_kepler_close_to_parabolic() {
+            for e in [0., 0.5, 0.9, 0.99, 0.999999] {
+                for m in mean_anomalies() {
+                    let solution = solve_kepler(m, e, KEPLER_TOLERANCE, KEPLER_MAX_ITERATIONS);
+                    let ecc_anom = solution.eccentric_anomaly;
+
+                    assert!(solution.converged);
+                    assert!(solution.iterations <= 15);
+                    assert!((ecc_anom - (e * ecc_anom.sin()) - m).abs() < 1e-12);
+                }
+            }
+        }
+
+        #[test]
+        fn solve_hyperbolic_kepler_close_to_parabolic() {
+            for e in [1.000001, 1.01, 1.5, 5.] {
+                for m in mean_anomalies().map(|m| m * 10.) {
+                    let solution =
+                        solve_hyperbolic_kepler(m, e, KEPLER_TOLERANCE, KEPLER_MAX_ITERATIONS);
+                    let hyp_anom = solution.eccentric_anomaly;
+
+                    assert!(solution.converged);
+                    assert!(((e * hyp_anom.sinh()) - hyp_anom - m).abs() < 1e-12 * m.abs().max(1.));
+                }
+            }
+        }
+
+        #[test]
+        fn solve_barker_solves_barkers_equation() {
+            for m in mean_anomalies() {
+                let d = solve_barker(m);
+
+                assert!((d + (d.powf(3.) / 3.) - (m / 2_f64.sqrt())).abs() < 1e-12);
+            }
+        }
+
+        #[test]
+        fn non_convergence_is_reported() {
+            let solution = solve_kepler(1., 0.9, 0., 3);
+            assert!(!solution.converged);
+            assert_eq!(solution.iterations, 3);
+
+            assert!(!kepler_solution(0.5, 1., f64::NAN).converged);
+            assert!(!kepler_solution(1.5, 1., f64::INFINITY).converged);
+            assert_eq!(true_anomaly_checked(0.5, 1., f64::NAN), None);
+            assert_eq!(
+                true_anomaly_checked(0.5, 1., 0.3),
+                Some(true_anomaly(0.5, 1., 0.3))
+            );
+        }
+
+        #[test]
+        fn eccentricities_next_to_1_are_parabolic() {
+            assert!(is_parabolic(1.));
+            assert!(is_parabolic(1. + 1e-11) && is_parabolic(1. - 1e-11));
+            assert!(!is_parabolic(1.000001) && !is_parabolic(0.999999));
+
+            //Same orbit as the exactly parabolic one, with a the periastron distance
+            for e in [1. - 1e-11, 1. + 1e-11] {
+                assert_eq!(true_anomaly(e, 1., 0.3), true_anomaly(1., 1., 0.3));
+                assert_eq!(semi_parameter(2., e), semi_parameter(2., 1.));
+                assert_eq!(perigee(2., e), 2.);
+                assert_eq!(apogee(2., e), f64::INFINITY);
+            }
+        }
+
+        #[test]
+        fn true_anomaly_matches_the_eccentric_anomaly() {
+            //At E = 90 degrees cos(v) = -e
+            let e = 0.999999;
+            let t_p = (std::f64::consts::FRAC_PI_2 - e) / (2. * std::f64::consts::PI);
+            assert!((true_anomaly(e, 1., t_p).cos() + e).abs() < 1e-12);
+
+            //Past periastron on a hyperbola the true anomaly approaches the asymptote acos(-1 / e)
+            let v = true_anomaly(1.000001, 1., 1e6);
+            assert!(v > 0. && v < (-1. / 1.000001_f64).acos());
+        }
+    }
+}
+
+/// Transform fucntions used by `spv-rs` but exposed her if you want to use them yourself.
+pub mod coordinate_transforms {
+    use glam::f64::{DMat3, DVec2, DVec3};
+
+    /// Method for getting base manipulation matrix that is used to rotate the companion star in a twobody system
+    /// relative to the earth/sun plane.
+    /// lotn is Longitude of the node (Omega) in degrees, aop is Argument of periastron (omega) in degrees and finally i is the Inclination in degrees.
+    /// Output is a 3-dimensional matrix with x1, x2 and x3 in the first collum, y1, y2 and y3 in the second collum and z1, z2 and z3 in the third collum.
+    pub fn euler_angle_transformations(lotn: f64, aop: f64, i: f64) -> DMat3 {
+        //In rad
+        let lotn_rad = lotn.to_radians();
+        let aop_rad = aop.to_radians();
+        let i_rad = i.to_radians();
+
+        //Ellipse base
+        let x1 = (lotn_rad.cos() * aop_rad.cos()) - (lotn_rad.sin() * i_rad.cos() * aop_rad.sin());
+        let x2 = (lotn_rad.sin() * aop_rad.cos()) + (lotn_rad.cos() * i_rad.cos() * aop_rad.sin());
+        let x3 = i_rad.sin() * aop_rad.sin();
+
+        let y1 = ((0. - lotn_rad.cos()) * aop_rad.sin())
+            - (lotn_rad.sin() * i_rad.cos() * aop_rad.cos());
+        let y2 = ((0. - lotn_rad.sin()) * aop_rad.sin())
+            + (lotn_rad.cos() * i_rad.cos() * aop_rad.cos());
+        let y3 = i_rad.sin() * aop_rad.cos();
+
+        let z1 = i_rad.sin() * lotn_rad.sin();
+        let z2 = (0. - i_rad.sin()) * lotn_rad.cos();
+        let z3 = i_rad.cos();
+
+        DMat3::from_cols(
+            DVec3::new(x1, x2, x3),
+            DVec3::new(y1, y2, y3),
+            DVec3::new(z1, z2, z3),
+        )
+    }
+
+    /// Rotation matrix from the equatorial (ICRS) frame to the galactic frame as defined for the Hipparcos catalogue (ESA 1997, Vol. 1, Sect. 1.5.3).
+    /// The galactic x-axis points towards the galactic center, the y-axis in the direction of galactic rotation and the z-axis towards the north galactic pole.
+    pub fn icrs_to_galactic_matrix() -> DMat3 {
+        DMat3::from_cols(
+            DVec3::new(-0.0548755604162154, 0.4941094278755837, -0.8676661490190047),
+            DVec3::new(-0.873437090234885, -0.4448296299600112, -0.1980763734312015),
+            DVec3::new(-0.4838350155487132, 0.746982244497219, 0.4559837761750669),
+        )
+    }
+
+    /// Rotation matrix from the galactic frame to the equatorial (ICRS) frame, the transpose of [`icrs_to_galactic_matrix`].
+    pub fn galactic_to_icrs_matrix() -> DMat3 {
+        icrs_to_galactic_matrix().transpose()
+    }
+
+    /// Rotates a position or velocity vector from the equatorial (ICRS) frame to the galactic frame.
+    /// Works on the outputs of [`super::position::position`] and [`super::velocity::velocity`], the units are kept as is.
+    pub fn icrs_to_galactic(vector: DVec3) -> DVec3 {
+        icrs_to_galactic_matrix() * vector
+    }
+
+    /// Rotates a position or velocity vector from the galactic frame to the equatorial (ICRS) frame.
+    pub fn galactic_to_icrs(vector: DVec3) -> DVec3 {
+        galactic_to_icrs_matrix() * vector
+    }
+
+    /// Converts right_ascension and declination in degrees to galactic longitude and latitude in degrees.
+    /// Output is a 2-dimensional vector with l and b in that order.
+    pub fn equatorial_to_galactic_angles(right_ascension: f64, declination: f64) -> DVec2 {
+        spherical_angles(icrs_to_galactic(unit_vector(right_ascension, declination)))
+    }
+
+    /// Converts galactic longitude l and latitude b in degrees to right ascension and declination in degrees.
+    /// Output is a 2-dimensional vector with right ascension and declination in that order.
+    pub fn galactic_to_equatorial_angles(l: f64, b: f64) -> DVec2 {
+        spherical_angles(galactic_to_icrs(unit_vector(l, b)))
+    }
+
+    /// Unit vector pointing towards longitude and latitude in degrees in any spherical frame.
+    pub fn unit_vector(longitude: f64, latitude: f64) -> DVec3 {
+        let (sin_lon, cos_lon) = longitude.to_radians().sin_cos();
+        let (sin_lat, cos_lat) = latitude.to_radians().sin_cos();
+
+        DVec3::new(cos_lat * cos_lon, cos_lat * sin_lon, sin_lat)
+    }
+
+    /// Longitude and latitude in degrees of the direction of vector in any spherical frame, the inverse of [`unit_vector`].
+    /// Output is a 2-dimensional vector with longitude (0 to 360) and latitude in that order.
+    pub fn spherical_angles(vector: DVec3) -> DVec2 {
+        DVec2::new(
+            vector.y.atan2(vector.x).to_degrees().rem_euclid(360.),
+            vector
+                .z
+                .atan2((vector.x.powf(2.) + vector.y.powf(2.)).sqrt())
+                .to_degrees(),
+        )
+    }
+
+    /// Mean obliquity of the ecliptic in degrees for epoch in Julian years (IAU 2006).
+    pub fn mean_obliquity(epoch: f64) -> f64 {
+        let t = (epoch - 2000.) / 100.;
+
+        (84381.406 - (46.836769 * t) - (0.0001831 * t.powf(2.)) + (0.0020034 * t.powf(3.))
+            - (0.000000576 * t.powf(4.))
+            - (0.0000000434 * t.powf(5.)))
+            / 3600.
+    }
+
+    /// Rotation matrix from an equatorial frame to the ecliptic frame with obliquity in degrees.
+    pub fn equatorial_to_ecliptic_matrix(obliquity: f64) -> DMat3 {
+        let (sin_eps, cos_eps) = obliquity.to_radians().sin_cos();
+
+        DMat3::from_cols(
+            DVec3::new(1., 0., 0.),
+            DVec3::new(0., cos_eps, -sin_eps),
+            DVec3::new(0., sin_eps, cos_eps),
+        )
+    }
+
+    /// Rotates a position or velocity vector from an equatorial frame to the ecliptic frame with obliquity in degrees, units are kept as is.
+    pub fn equatorial_to_ecliptic(vector: DVec3, obliquity: f64) -> DVec3 {
+        equatorial_to_ecliptic_matrix(obliquity) * vector
+    }
+
+    /// Rotates a position or velocity vector from the ecliptic frame with obliquity in degrees to the equatorial frame, units are kept as is.
+    pub fn ecliptic_to_equatorial(vector: DVec3, obliquity: f64) -> DVec3 {
+        equatorial_to_ecliptic_matrix(obliquity).transpose() * vector
+    }
+
+    /// Rotates a position or velocity vector from the equatorial (ICRS) frame to the mean ecliptic and equinox of J2000.
+    /// The frame bias between ICRS and the mean equator of J2000 (about 23 mas) is neglected here.
+    /// Use this for companion orbits whose elements are given relative to the ecliptic together with [`ecliptic_j2000_to_icrs`].
+    pub fn icrs_to_ecliptic_j2000(vector: DVec3) -> DVec3 {
+        equatorial_to_ecliptic(vector, mean_obliquity(2000.))
+    }
+
+    /// Rotates a position or velocity vector from the mean ecliptic and equinox of J2000 to the equatorial (ICRS) frame.
+    /// For example the output of [`super::position::companion_relative_position`] for elements referred to the ecliptic.
+    pub fn ecliptic_j2000_to_icrs(vector: DVec3) -> DVec3 {
+        ecliptic_to_equatorial(vector, mean_obliquity(2000.))
+    }
+
+    /// Rotates a position or velocity vector from the mean equator and equinox of date to the mean ecliptic and equinox of date,
+    /// epoch is the date in Julian years. The slow rotation of the frame of date itself is neglected for velocities.
+    pub fn equatorial_to_ecliptic_of_date(vector: DVec3, epoch: f64) -> DVec3 {
+        equatorial_to_ecliptic(vector, mean_obliquity(epoch))
+    }
+
+    /// Rotates a position or velocity vector from the mean ecliptic and equinox of date to the mean equator and equinox of date,
+    /// epoch is the date in Julian years.
+    pub fn ecliptic_of_date_to_equatorial(vector: DVec3, epoch: f64) -> DVec3 {
+        ecliptic_to_equatorial(vector, mean_obliquity(epoch))
+    }
+
+    /// Converts right_ascension and declination in degrees to ecliptic longitude and latitude in degrees with obliquity in degrees.
+    /// Output is a 2-dimensional vector with ecliptic longitude and latitude in that order.
+    pub fn equatorial_to_ecliptic_angles(
+        right_ascension: f64,
+        declination: f64,
+        obliquity: f64,
+    ) -> DVec2 {
+        spherical_angles(equatorial_to_ecliptic(
+            unit_vector(right_ascension, declination),
+            obliquity,
+        ))
+    }
+
+    /// Converts ecliptic longitude and latitude in degrees to right ascension and declination in degrees with obliquity in degrees.
+    /// Output is a 2-dimensional vector with right ascension and declination in that order.
+    pub fn ecliptic_to_equatorial_angles(longitude: f64, latitude: f64, obliquity: f64) -> DVec2 {
+        spherical_angles(ecliptic_to_equatorial(
+            unit_vector(longitude, latitude),
+            obliquity,
+        ))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn galactic_angles_of_reference_directions() {
+            //North galactic pole and galactic center from the Hipparcos definition (ESA 1997)
+            let pole = equatorial_to_galactic_angles(192.85948, 27.12825);
+            assert!((pole.y - 90.).abs() < 1e-6);
+
+            let center = equatorial_to_galactic_angles(266.40499, -28.93617);
+            assert!(center.x.min(360. - center.x) < 1e-5);
+            assert!(center.y.abs() < 1e-5);
+
+            //Galactic longitude of the north celestial pole
+            let celestial_pole = equatorial_to_galactic_angles(0., 90.);
+            assert!((celestial_pole.x - 122.93192).abs() < 1e-5);
+        }
+
+        #[test]
+        fn galactic_rotation_round_trip() {
+            let matrix = icrs_to_galactic_matrix() * galactic_to_icrs_matrix();
+            assert!(matrix.abs_diff_eq(DMat3::IDENTITY, 1e-15));
+            assert!((icrs_to_galactic_matrix().determinant() - 1.).abs() < 1e-15);
+
+            let angles = galactic_to_equatorial_angles(33.3, -12.5);
+            let back = equatorial_to_galactic_angles(angles.x, angles.y);
+            assert!((back - DVec2::new(33.3, -12.5)).length() < 1e-12);
+        }
+
+        #[test]
+        fn mean_obliquity_matches_sofa() {
+            //SOFA iauObl06 at MJD 54388.0
+            let epoch = 2000. + ((2400000.5 + 54388.0 - 2451545.) / 365.25);
+            assert!((mean_obliquity(epoch).to_radians() - 0.409_074_922_938_725_8).abs() < 1e-14);
+            assert!((mean_obliquity(2000.) * 3600. - 84381.406).abs() < 1e-9);
+        }
+
+        #[test]
+        fn ecliptic_angles_of_pollux() {
+            //Meeus, Astronomical Algorithms, example 13.a
+            let ecliptic = equatorial_to_ecliptic_angles(116.328942, 28.026183, 23.4392911);
+            assert!((ecliptic.x - 113.215630).abs() < 1e-6);
+            assert!((ecliptic.y - 6.684170).abs() < 1e-6);
+
+            let equatorial = ecliptic_to_equatorial_angles(ecliptic.x, ecliptic.y, 23.4392911);
+            assert!((equatorial - DVec2::new(116.328942, 28.026183)).length() < 1e-12);
+        }
+
+        #[test]
+        fn ecliptic_pole_and_round_trips() {
+            let pole = ecliptic_j2000_to_icrs(DVec3::Z);
+            let angles = spherical_angles(pole);
+            assert!((angles.x - 270.).abs() < 1e-12);
+            assert!((angles.y - (90. - mean_obliquity(2000.))).abs() < 1e-12);
+
+            let vector = DVec3::new(1.5, -2., 0.25);
+            assert!(
+                (icrs_to_ecliptic_j2000(ecliptic_j2000_to_icrs(vector)) - vector).length() < 1e-15
+            );
+            let of_date = equatorial_to_ecliptic_of_date(vector, 2150.);
+            assert!((ecliptic_of_date_to_equatorial(of_date, 2150.) - vector).length() < 1e-15);
+        }
+    }
+}
+
+/// Astrometric parameters and epoch propagation for catalogs that are not referred to J2000 (Gaia DR3 is at J2016.0 and Hipparcos at J1991.25).
+/// The propagation is the rigorous uniform space motion model from the Hipparcos catalogue (ESA 1997, Vol. 1, Sect. 1.5.5) and includes perspective acceleration.
+pub mod astrometry {
+    use super::common::PARSEC;
+    use super::coordinate_transforms::spherical_angles;
+    use super::time::JULIAN_YEAR;
+    use glam::f64::DVec3;
+
+    /// One astronomical unit per Julian year expressed in km/s.
+    pub const AU_PER_YEAR_KM_S: f64 = 4.740470463533348;
+
+    /// The six astrometric parameters of a single celestial object at some epoch.
+    /// parallax is in mas (milliarcseconds), right_ascension is in degrees and declination in degrees,
+    /// proper_motion_ra is the rate of change of right ascension (not multiplied by cos(declination)) in as/yr (arcseconds per year),
+    /// proper_motion_dec is in as/yr (arcseconds per year) and radial_velocity is in km/s.
+    /// These are the same units and conventions used by [`super::position::position`] and [`super::velocity::velocity`].
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct AstrometricParameters {
+        pub parallax: f64,
+        pub right_ascension: f64,
+        pub declination: f64,
+        pub proper_motion_ra: f64,
+        pub proper_motion_dec: f64,
+        pub radial_velocity: f64,
+    }
+
+    impl AstrometricParameters {
+        /// Creates a new set of astrometric parameters, see [`AstrometricParameters`] for the units.
+        pub fn new(
+            parallax: f64,
+            right_ascension: f64,
+            declination: f64,
+            proper_motion_ra: f64,
+            proper_motion_dec: f64,
+            radial_velocity: f64,
+        ) -> Self {
+            Self {
+                parallax,
+                right_ascension,
+                declination,
+                proper_motion_ra,
+                proper_motion_dec,
+                radial_velocity,
+            }
+        }
+    }
+
+    /// Normal triad (p, q, r) at right_ascension and declination in degrees.
+    /// p points towards increasing right ascension, q towards increasing declination and r towards the object.
+    /// Output is a 3-dimensional matrix with p in the first collum, q in the second collum and r in the third collum.
+    pub fn normal_triad(right_ascension: f64, declination: f64) -> glam::f64::DMat3 {
+        let right_ascension_rad = right_ascension.to_radians();
+        let declination_rad = declination.to_radians();
+
+        let (sin_ra, cos_ra) = right_ascension_rad.sin_cos();
+        let (sin_dec, cos_dec) = declination_rad.sin_cos();
+
+        glam::f64::DMat3::from_cols(
+            DVec3::new(-sin_ra, cos_ra, 0.),
+            DVec3::new(-sin_dec * cos_ra, -sin_dec * sin_ra, cos_dec),
+            DVec3::new(cos_dec * cos_ra, cos_dec * sin_ra, sin_dec),
+        )
+    }
+
+    /// Propagates astrometric parameters from epoch to target_epoch, both given as Julian years (e.g. 2016.0 for Gaia DR3).
+    /// The object is assumed to move in a straight line with constant velocity relative to the sun, which means that the
+    /// change in radial velocity and proper motion over time (perspective acceleration) is included.
+    /// If the parallax is zero or negative the radial velocity cannot be turned into a motion along the line of sight and it is ignored.
+    pub fn propagate_epoch(
+        astrometry: AstrometricParameters,
+        epoch: f64,
+        target_epoch: f64,
+    ) -> AstrometricParameters {
+        let tau = target_epoch - epoch;
+
+        //Proper motions in rad/yr with the cos(declination) factor applied
+        let as_to_rad = (1. / 3600_f64).to_radians();
+        let pm_ra_star =
+            astrometry.proper_motion_ra * astrometry.declination.to_radians().cos() * as_to_rad;
+        let pm_dec = astrometry.proper_motion_dec * as_to_rad;
+
+        //Radial proper motion in rad/yr
+        let pm_r = if astrometry.parallax > 0. {
+            astrometry.radial_velocity * (astrometry.parallax / 1000.) * as_to_rad
+                / AU_PER_YEAR_KM_S
+        } else {
+            0.
+        };
+
+        let triad = normal_triad(astrometry.right_ascension, astrometry.declination);
+        let p0 = triad.x_axis;
+        let q0 = triad.y_axis;
+        let r0 = triad.z_axis;
+
+        let pm_vector_0 = (p0 * pm_ra_star) + (q0 * pm_dec);
+        let pm_total_sq = pm_ra_star.powf(2.) + pm_dec.powf(2.);
+
+        //Distance factor
+        let f =
+            1. / (1. + (2. * pm_r * tau) + ((pm_total_sq + pm_r.powf(2.)) * tau.powf(2.))).sqrt();
+
+        //Direction and proper motion vector at the target epoch
+        let u = (r0 * (1. + (pm_r * tau))) + (pm_vector_0 * tau);
+        let r = u * f;
+        let pm_vector =
+            ((pm_vector_0 * (1. + (pm_r * tau))) - (r0 * (pm_total_sq * tau))) * f.powf(3.);
+
+        let parallax = astrometry.parallax * f;
+        let pm_r_new = (pm_r + ((pm_total_sq + pm_r.powf(2.)) * tau)) * f.powf(2.);
+
+        let declination = r.z.atan2((r.x.powf(2.) + r.y.powf(2.)).sqrt()).to_degrees();
+        let right_ascension = r.y.atan2(r.x).to_degrees().rem_euclid(360.);
+
+        let new_triad = normal_triad(right_ascension, declination);
+        let pm_ra_star_new = new_triad.x_axis.dot(pm_vector);
+        let pm_dec_new = new_triad.y_axis.dot(pm_vector);
+
+        let radial_velocity = if astrometry.parallax > 0. {
+            pm_r_new * AU_PER_YEAR_KM_S / ((parallax / 1000.) * as_to_rad)
+        } else {
+            astrometry.radial_velocity
+        };
+
+        AstrometricParameters {
+            parallax,
+            right_ascension,
+            declination,
+            proper_motion_ra: pm_ra_star_new / declination.to_radians().cos() / as_to_rad,
+            proper_motion_dec: pm_dec_new / as_to_rad,
+            radial_velocity,
+        }
+    }
+
+    /// Speed of light in meters/second.
+    pub const SPEED_OF_LIGHT: f64 = 299792458.;
+
+    /// Instantaneous rates of change per Julian year of the astrometric parameters caused by the object moving in a straight line
+    /// (perspective or secular acceleration). Each field of the output holds the rate of the field with the same name in astrometry:
+    /// parallax in mas/yr, right_ascension and declination in degrees/yr, proper_motion_ra and proper_motion_dec in as/yr² and radial_velocity in km/s/yr.
+    /// The proper motion rates include the terms from the curvature of the sphere, so they are the rates of the catalog columns themselves.
+    pub fn secular_rates(astrometry: AstrometricParameters) -> AstrometricParameters {
+        let as_to_rad = (1. / 3600_f64).to_radians();
+        let (sin_dec, cos_dec) = astrometry.declination.to_radians().sin_cos();
+        let tan_dec = sin_dec / cos_dec;
+
+        //Rates in rad/yr
+        let pm_ra_star = astrometry.proper_motion_ra * cos_dec * as_to_rad;
+        let pm_dec = astrometry.proper_motion_dec * as_to_rad;
+        let pm_r = if astrometry.parallax > 0. {
+            astrometry.radial_velocity * (astrometry.parallax / 1000.) * as_to_rad
+                / AU_PER_YEAR_KM_S
+        } else {
+            0.
+        };
+        let pm_total_sq = pm_ra_star.powf(2.) + pm_dec.powf(2.);
+
+        //Rates of the proper motion components on the sky in rad/yr²
+        let pm_ra_star_rate = (-2. * pm_r * pm_ra_star) + (pm_ra_star * pm_dec * tan_dec);
+        let pm_dec_rate = (-2. * pm_r * pm_dec) - (pm_ra_star.powf(2.) * tan_dec);
+
+        //Rate of pmRA = pmRA* / cos(declination)
+        let pm_ra_rate = (pm_ra_star_rate + (pm_ra_star * pm_dec * tan_dec)) / cos_dec;
+
+        let radial_velocity_rate = if astrometry.parallax > 0. {
+            pm_total_sq * AU_PER_YEAR_KM_S / ((astrometry.parallax / 1000.) * as_to_rad)
+        } else {
+            0.
+        };
+
+        AstrometricParameters {
+            parallax: -astrometry.parallax * pm_r,
+            right_ascension: astrometry.proper_motion_ra / 3600.,
+            declination: astrometry.proper_motion_dec / 3600.,
+            proper_motion_ra: pm_ra_rate / as_to_rad,
+            proper_motion_dec: pm_dec_rate / as_to_rad,
+            radial_velocity: radial_velocity_rate,
+        }
+    }
+
+    /// Light travel time in seconds from an object at position (meters, e.g. from [`super::position::position`]) to the sun.
+    pub fn light_travel_time(position: DVec3) -> f64 {
+        position.length() / SPEED_OF_LIGHT
+    }
+
+    /// Instantaneous (geometric) position in meters of an object moving with velocity in meters/second given its
+    /// retarded position in meters, which is where the object was when the light we see left it (what a catalog gives).
+    pub fn instantaneous_position(retarded_position: DVec3, velocity: DVec3) -> DVec3 {
+        retarded_position + (velocity * light_travel_time(retarded_position))
+    }
+
+    /// Retarded position in meters of an object moving with velocity in meters/second given its instantaneous position in meters,
+    /// the inverse of [`instantaneous_position`]. Solves |r - v * t| = c * t for the light travel time t.
+    pub fn retarded_position(instantaneous_position: DVec3, velocity: DVec3) -> DVec3 {
+        let r_dot_v = instantaneous_position.dot(velocity);
+        let c_sq_minus_v_sq = SPEED_OF_LIGHT.powf(2.) - velocity.length_squared();
+
+        let time = ((r_dot_v.powf(2.)
+            + (c_sq_minus_v_sq * instantaneous_position.length_squared()))
+        .sqrt()
+            - r_dot_v)
+            / c_sq_minus_v_sq;
+
+        instantaneous_position - (velocity * time)
+    }
+
+    /// Inverse of [`super::position::position`] and [`super::velocity::velocity`], turns a heliocentric cartesian state back into astrometric observables.
+    /// position is in meters and velocity in meters/second, for example from NBSS output placed relative to the sun.
+    /// Output is in the same units that the forward functions accept, see [`AstrometricParameters`].
+    pub fn astrometry_from_state(position: DVec3, velocity: DVec3) -> AstrometricParameters {
+        let distance_si = position.length();
+        let angles = spherical_angles(position);
+        let triad = normal_triad(angles.x, angles.y);
+
+        //rad/s to as/yr with the year used by velocity
+        let rad_per_s_to_as_per_yr = JULIAN_YEAR / (1. / 3600_f64).to_radians();
+
+        let proper_motion_ra_star = velocity.dot(triad.x_axis) / distance_si;
+        let proper_motion_dec = velocity.dot(triad.y_axis) / distance_si;
+
+        AstrometricParameters {
+            parallax: 1000. * PARSEC / distance_si,
+            right_ascension: angles.x,
+            declination: angles.y,
+            proper_motion_ra: proper_motion_ra_star / angles.y.to_radians().cos()
+                * rad_per_s_to_as_per_yr,
+            proper_motion_dec: proper_motion_dec * rad_per_s_to_as_per_yr,
+            radial_velocity: velocity.dot(triad.z_axis) / 1000.,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::position::position;
+        use crate::velocity::velocity;
+
+        //Barnard's star from Gaia DR3 with proper_motion_ra converted to the rate of right ascension
+        fn barnard() -> AstrometricParameters {
+            AstrometricParameters::new(
+                546.9759,
+                269.4486,
+                4.7393,
+                -0.80235 / 4.7393_f64.to_radians().cos(),
+                10.36224,
+                -110.11,
+            )
+        }
+
+        #[test]
+        fn propagate_epoch_zero_interval_is_identity() {
+            let star = barnard();
+            let propagated = propagate_epoch(star, 2016., 2016.);
+
+            assert!((propagated.parallax - star.parallax).abs() < 1e-12);
+            assert!((propagated.right_ascension - star.right_ascension).abs() < 1e-12);
+            assert!((propagated.declination - star.declination).abs() < 1e-12);
+            assert!((propagated.proper_motion_ra - star.proper_motion_ra).abs() < 1e-12);
+            assert!((propagated.proper_motion_dec - star.proper_motion_dec).abs() < 1e-12);
+            assert!((propagated.radial_velocity - star.radial_velocity).abs() < 1e-9);
+        }
+
+        #[test]
+        fn propagate_epoch_matches_straight_line_motion() {
+            let star = barnard();
+            let years = 10000.;
+
+            let start = position(star.parallax, star.right_ascension, star.declination);
+            let speed = velocity(
+                star.parallax,
+                star.right_ascension,
+                star.declination,
+                star.proper_motion_ra,
+                star.proper_motion_dec,
+                star.radial_velocity,
+            );
+            let expected = astrometry_from_state(start + (speed * years * JULIAN_YEAR), speed);
+
+            let propagated = propagate_epoch(star, 2016., 2016. + years);
+
+            //The parsec used by position differs from the IAU value behind AU_PER_YEAR_KM_S by about 1e-7
+            assert!((propagated.parallax / expected.parallax - 1.).abs() < 1e-6);
+            assert!((propagated.right_ascension - expected.right_ascension).abs() < 1e-5);
+            assert!((propagated.declination - expected.declination).abs() < 1e-5);
+            assert!((propagated.proper_motion_ra / expected.proper_motion_ra - 1.).abs() < 1e-6);
+            assert!((propagated.proper_motion_dec / expected.proper_motion_dec - 1.).abs() < 1e-6);
+            assert!((propagated.radial_velocity - expected.radial_velocity).abs() < 1e-4);
+        }
+
+        #[test]
+        fn propagate_epoch_is_reversible() {
+            let star = barnard();
+            let back = propagate_epoch(propagate_epoch(star, 2016., -3000.), -3000., 2016.);
+
+            assert!((back.parallax - star.parallax).abs() < 1e-9);
+            assert!((back.right_ascension - star.right_ascension).abs() < 1e-10);
+            assert!((back.declination - star.declination).abs() < 1e-10);
+            assert!((back.proper_motion_ra - star.proper_motion_ra).abs() < 1e-10);
+            assert!((back.proper_motion_dec - star.proper_motion_dec).abs() < 1e-10);
+            assert!((back.radial_velocity - star.radial_velocity).abs() < 1e-6);
+        }
+
+        #[test]
+        fn astrometry_from_state_round_trip() {
+            let stars = [
+                barnard(),
+                AstrometricParameters::new(0.35, 359.9, -72.5, -0.0004, 0.0021, -310.),
+                AstrometricParameters::new(768.0665, 217.39, -62.68, -3.78 / 0.459, 0.77, -22.4),
+                AstrometricParameters::new(2.1, 0.01, 89.2, 0.15, -0.01, 12.),
+            ];
+
+            for star in stars {
+                let state_position =
+                    position(star.parallax, star.right_ascension, star.declination);
+                let state_velocity = velocity(
+                    star.parallax,
+                    star.right_ascension,
+                    star.declination,
+                    star.proper_motion_ra,
+                    star.proper_motion_dec,
+                    star.radial_velocity,
+                );
+
+                let back = astrometry_from_state(state_position, state_velocity);
+
+                assert!((back.parallax / star.parallax - 1.).abs() < 1e-13);
+                assert!((back.right_ascension - star.right_ascension).abs() < 1e-10);
+                assert!((back.declination - star.declination).abs() < 1e-12);
+                assert!((back.proper_motion_ra - star.proper_motion_ra).abs() < 1e-10);
+                assert!((back.proper_motion_dec - star.proper_motion_dec).abs() < 1e-12);
+                assert!((back.radial_velocity - star.radial_velocity).abs() < 1e-10);
+            }
+        }
+
+        #[test]
+        fn secular_rates_match_the_propagation() {
+            let star = barnard();
+            let rates = secular_rates(star);
+            let plus = propagate_epoch(star, 2016., 2016.5);
+            let minus = propagate_epoch(star, 2016., 2015.5);
+
+            assert!((rates.parallax - (plus.parallax - minus.parallax)).abs() < 1e-9);
+            assert!(
+                (rates.right_ascension - (plus.right_ascension - minus.right_ascension)).abs()
+                    < 1e-10
+            );
+            assert!((rates.declination - (plus.declination - minus.declination)).abs() < 1e-10);
+            assert!(
+                (rates.proper_motion_ra - (plus.proper_motion_ra - minus.proper_motion_ra)).abs()
+                    < 1e-9
+            );
+            assert!(
+                (rates.proper_motion_dec - (plus.proper_motion_dec - minus.proper_motion_dec))
+                    .abs()
+                    < 1e-9
+            );
+            assert!(
+                (rates.radial_velocity - (plus.radial_velocity - minus.radial_velocity)).abs()
+                    < 1e-9
+            );
+
+            //Barnard's star has the largest known secular acceleration, about 4.5 m/s per year in radial velocity
+            assert!((rates.radial_velocity * 1000. - 4.5).abs() < 0.1);
+            assert!((rates.proper_motion_dec * 1000. - 1.28).abs() < 0.05);
+        }
+
+        #[test]
+        fn light_time_corrections_are_inverse() {
+            let retarded = position(10., 100., 30.);
+            let speed = velocity(10., 100., 30., 0.5, -0.3, -80.);
+
+            //About 326 years for 100 pc
+            let years = light_travel_time(retarded) / JULIAN_YEAR;
+            assert!((years - 326.156).abs() < 0.001);
+
+            let instantaneous = instantaneous_position(retarded, speed);
+            let back = retarded_position(instantaneous, speed);
+            //The light travel time is measured from the retarded position so this is exact
+            assert!((back - retarded).length() / retarded.length() < 1e-14);
+            let moved = (instantaneous - retarded).length();
+            assert!((moved / (speed.length() * years * JULIAN_YEAR) - 1.).abs() < 1e-12);
+        }
+    }
+}
+
+/// Galactocentric frame with configurable solar parameters, used to place stars in the Milky Way for galactic dynamics.
+/// The frame is right-handed with the galactic center at the origin, the sun on the negative x-axis and the z-axis towards the north galactic pole.
+/// This means that stars in the disk rotating with the galaxy have a negative v_phi.
+pub mod galactocentric {
+    use super::common::PARSEC;
+    use super::coordinate_transforms::icrs_to_galactic;
+    use glam::f64::{DMat3, DVec3};
+
+    /// Solar parameters that define the galactocentric frame.
+    /// sun_gc_distance is the distance between the sun and the galactic center in kpc, sun_height is the height of the sun above the galactic plane in pc,
+    /// solar_peculiar_motion is the motion of the sun relative to the local standard of rest as U, V and W in km/s
+    /// and lsr_velocity is the circular velocity of the local standard of rest in km/s.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct GalactocentricFrame {
+        pub sun_gc_distance: f64,
+        pub sun_height: f64,
+        pub solar_peculiar_motion: DVec3,
+        pub lsr_velocity: f64,
+    }
+
+    impl Default for GalactocentricFrame {
+        /// Distance from GRAVITY Collaboration (2018), height from Bennett & Bovy (2019),
+        /// peculiar motion from Schönrich, Binney & Dehnen (2010) and an LSR velocity that makes the total solar motion match Reid & Brunthaler (2004).
+        fn default() -> Self {
+            Self {
+                sun_gc_distance: 8.122,
+                sun_height: 20.8,
+                solar_peculiar_motion: DVec3::new(11.1, 12.24, 7.25),
+                lsr_velocity: 233.4,
+            }
+        }
+    }
+
+    impl GalactocentricFrame {
+        /// Creates a new galactocentric frame, see [`GalactocentricFrame`] for the units.
+        pub fn new(
+            sun_gc_distance: f64,
+            sun_height: f64,
+            solar_peculiar_motion: DVec3,
+            lsr_velocity: f64,
+        ) -> Self {
+            Self {
+                sun_gc_distance,
+                sun_height,
+                solar_peculiar_motion,
+                lsr_velocity,
+            }
+        }
+
+        /// Distance between the sun and the galactic center in meters.
+        pub fn sun_gc_distance_si(&self) -> f64 {
+            self.sun_gc_distance * PARSEC * 1000.
+        }
+
+        /// Velocity of the sun in the galactocentric frame in m/s.
+        pub fn solar_velocity_si(&self) -> DVec3 {
+            (self.solar_peculiar_motion + DVec3::new(0., self.lsr_velocity, 0.)) * 1000.
+        }
+
+        /// Rotation around the y-axis that lifts the sun sun_height above the galactic plane.
+        pub fn tilt(&self) -> DMat3 {
+            let theta = ((self.sun_height / 1000.) / self.sun_gc_distance).asin();
+            let (sin_theta, cos_theta) = theta.sin_cos();
+
+            DMat3::from_cols(
+                DVec3::new(cos_theta, 0., -sin_theta),
+                DVec3::new(0., 1., 0.),
+                DVec3::new(sin_theta, 0., cos_theta),
+            )
+        }
+    }
+
+    /// Galactocentric position of a single celestial object.
+    /// position is the heliocentric position in meters as given by [`super::position::position`].
+    /// Output is a 3-dimensional vector with x, y and z in that order all in meters.
+    pub fn galactocentric_position(position: DVec3, frame: &GalactocentricFrame) -> DVec3 {
+        let galactic = icrs_to_galactic(position);
+
+        frame.tilt() * (galactic - DVec3::new(frame.sun_gc_distance_si(), 0., 0.))
+    }
+
+    /// Galactocentric velocity of a single celestial object.
+    /// velocity is the heliocentric velocity in meters/second as given by [`super::velocity::velocity`].
+    /// Output is a 3-dimensional vector with x, y and z in that order all in meters/second.
+    pub fn galactocentric_velocity(velocity: DVec3, frame: &GalactocentricFrame) -> DVec3 {
+        frame.tilt() * icrs_to_galactic(velocity) + frame.solar_velocity_si()
+    }
+
+    /// Cylindrical coordinates of a galactocentric position in meters.
+    /// Output is a 3-dimensional vector with R in meters, phi in degrees and z in meters in that order.
+    pub fn cylindrical_position(position: DVec3) -> DVec3 {
+        DVec3::new(
+            (position.x.powf(2.) + position.y.powf(2.)).sqrt(),
+            position.y.atan2(position.x).to_degrees(),
+            position.z,
+        )
+    }
+
+    /// Cylindrical velocity components of a galactocentric position in meters and velocity in meters/second.
+    /// Output is a 3-dimensional vector with v_R, v_phi and v_z in that order all in meters/second.
+    pub fn cylindrical_velocity(position: DVec3, velocity: DVec3) -> DVec3 {
+        let r = (position.x.powf(2.) + position.y.powf(2.)).sqrt();
+        let cos_phi = position.x / r;
+        let sin_phi = position.y / r;
+
+        DVec3::new(
+            (velocity.x * cos_phi) + (velocity.y * sin_phi),
+            (velocity.y * cos_phi) - (velocity.x * sin_phi),
+            velocity.z,
+        )
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::position::position;
+
+        #[test]
+        fn sun_is_at_the_solar_parameters() {
+            let frame = GalactocentricFrame::default();
+            let sun = galactocentric_position(DVec3::ZERO, &frame);
+
+            assert!((sun.length() - frame.sun_gc_distance_si()).abs() < 1.);
+            assert!(sun.x < 0.);
+            assert!(sun.y.abs() < 1e-6);
+            assert!((sun.z - (frame.sun_height * PARSEC)).abs() < 1e3);
+
+            let sun_velocity = galactocentric_velocity(DVec3::ZERO, &frame);
+            assert!((sun_velocity - DVec3::new(11.1, 245.64, 7.25) * 1000.).length() < 1e-6);
+        }
+
+        #[test]
+        fn star_at_the_galactic_center_is_at_the_origin() {
+            let frame = GalactocentricFrame::default();
+            let center = position(1. / frame.sun_gc_distance, 266.40499, -28.93617);
+
+            let galactocentric = galactocentric_position(center, &frame);
+            assert!(galactocentric.length() < 1e-6 * frame.sun_gc_distance_si());
+        }
+
+        #[test]
+        fn disk_rotation_has_negative_v_phi() {
+            let frame = GalactocentricFrame::default();
+            let sun = galactocentric_position(DVec3::ZERO, &frame);
+            let sun_velocity = galactocentric_velocity(DVec3::ZERO, &frame);
+
+            let cylindrical = cylindrical_position(sun);
+            assert!((cylindrical.y.abs() - 180.).abs() < 1e-9);
+            assert!((cylindrical.x - (sun.x.powf(2.) + sun.y.powf(2.)).sqrt()).abs() < 1e-6);
+
+            let velocity = cylindrical_velocity(sun, sun_velocity);
+            assert!((velocity.y + 245640.).abs() < 1e-6);
+            assert!((velocity.x + 11100.).abs() < 1e-6);
+            assert!((velocity.z - 7250.).abs() < 1e-9);
+        }
+    }
+}
+
+/// Analytic Milky Way potential made of a Hernquist bulge, a Miyamoto-Nagai disk and an NFW halo, for integrating orbits in galactocentric coordinates
+/// from [`super::galactocentric`]. Masses are in solar masses and lengths in kpc, positions are in meters, accelerations in meters/second^2 and potentials in joules/kg.
+pub mod galactic_potential {
+    use super::common::PARSEC;
+    use glam::f64::DVec3;
+
+    /// Heliocentric gravitational constant (G times the mass of the sun) in m^3/s^2.
+    pub const GM_SUN: f64 = 1.32712440018e20;
+
+    //Meters in a kpc
+    const KPC: f64 = PARSEC * 1000.;
+
+    /// Hernquist sphere with mass in solar masses and scale_radius in kpc.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Hernquist {
+        pub mass: f64,
+        pub scale_radius: f64,
+    }
+
+    /// Miyamoto-Nagai disk with mass in solar masses, scale_length a and scale_height b in kpc.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct MiyamotoNagai {
+        pub mass: f64,
+        pub a: f64,
+        pub b: f64,
+    }
+
+    /// NFW halo with scale mass in solar masses and scale_radius in kpc, the potential is -G * mass * ln(1 + r / scale_radius) / r.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Nfw {
+        pub mass: f64,
+        pub scale_radius: f64,
+    }
+
+    impl Hernquist {
+        /// Creates a new Hernquist sphere, see [`Hernquist`] for the units.
+        pub fn new(mass: f64, scale_radius: f64) -> Self {
+            Self { mass, scale_radius }
+        }
+
+        /// Potential in joules/kg at position in meters.
+        pub fn potential(&self, position: DVec3) -> f64 {
+            -GM_SUN * self.mass / (position.length() + (self.scale_radius * KPC))
+        }
+
+        /// Acceleration in meters/second^2 at position in meters.
+        pub fn acceleration(&self, position: DVec3) -> DVec3 {
+            let r = position.length();
+            if r == 0. {
+                return DVec3::ZERO;
+            }
+
+            -position / r * GM_SUN * self.mass / (r + (self.scale_radius * KPC)).powf(2.)
+        }
+    }
+
+    impl MiyamotoNagai {
+        /// Creates a new Miyamoto-Nagai disk, see [`MiyamotoNagai`] for the units.
+        pub fn new(mass: f64, a: f64, b: f64) -> Self {
+            Self { mass, a, b }
+        }
+
+        /// Potential in joules/kg at position in meters.
+        pub fn potential(&self, position: DVec3) -> f64 {
+            let r_sq = position.x.powf(2.) + position.y.powf(2.);
+            let zb = (position.z.powf(2.) + (self.b * KPC).powf(2.)).sqrt();
+
+            -GM_SUN * self.mass / (r_sq + ((self.a * KPC) + zb).powf(2.)).sqrt()
+        }
+
+        /// Acceleration in meters/second^2 at position in meters.
+        pub fn acceleration(&self, position: DVec3) -> DVec3 {
+            let r_sq = position.x.powf(2.) + position.y.powf(2.);
+            let zb = (position.z.powf(2.) + (self.b * KPC).powf(2.)).sqrt();
+            let a_zb = (self.a * KPC) + zb;
+            let factor = -GM_SUN * self.mass / (r_sq + a_zb.powf(2.)).powf(1.5);
+
+            DVec3::new(
+                factor * position.x,
+                factor * position.y,
+                factor * position.z * a_zb / zb,
+            )
+        }
+    }
+
+    impl Nfw {
+        /// Creates a new NFW halo, see [`Nfw`] for the units.
+        pub fn new(mass: f64, scale_radius: f64) -> Self {
+            Self { mass, scale_radius }
+        }
+
+        /// Potential in joules/kg at position in meters.
+        pub fn potential(&self, position: DVec3) -> f64 {
+            let r = position.length();
+            let r_s = self.scale_radius * KPC;
+            if r == 0. {
+                return -GM_SUN * self.mass / r_s;
+            }
+
+            -GM_SUN * self.mass * (1. + (r / r_s)).ln() / r
+        }
+
+        /// Acceleration in meters/second^2 at position in meters.
+        pub fn acceleration(&self, position: DVec3) -> DVec3 {
+            let r = position.length();
+            if r == 0. {
+                return DVec3::ZERO;
+            }
+            let r_s = self.scale_radius * KPC;
+
+            //d(potential)/dr
+            let gradient = GM_SUN
+                * self.mass
+                * (((1. + (r / r_s)).ln() / r.powf(2.)) - (1. / (r * (r + r_s))));
+
+            -position / r * gradient
+        }
+    }
+
+    /// Milky Way potential, the sum of a nucleus, a bulge, a disk and a halo.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct MilkyWayPotential {
+        pub nucleus: Hernquist,
+        pub bulge: Hernquist,
+        pub disk: MiyamotoNagai,
+        pub halo: Nfw,
+    }
+
+    impl Default for MilkyWayPotential {
+        /// Parameters of Bovy (2015) as used by the MilkyWayPotential of gala, giving a circular velocity of about 230 km/s at the sun.
+        fn default() -> Self {
+            Self {
+                nucleus: Hernquist::new(1.71e9, 0.07),
+                bulge: Hernquist::new(5e9, 1.),
+                disk: MiyamotoNagai::new(6.8e10, 3., 0.28),
+                halo: Nfw::new(5.4e11, 15.62),
+            }
+        }
+    }
+
+    impl MilkyWayPotential {
+        /// Creates a Milky Way potential from its components, see [`MilkyWayPotential::default`] for the usual parameters.
+        pub fn new(nucleus: Hernquist, bulge: Hernquist, disk: MiyamotoNagai, halo: Nfw) -> Self {
+            Self {
+                nucleus,
+                bulge,
+                disk,
+                halo,
+            }
+        }
+
+        /// Potential in joules/kg at galactocentric position in meters.
+        pub fn potential(&self, position: DVec3) -> f64 {
+            self.nucleus.potential(position)
+                + self.bulge.potential(position)
+                + self.disk.potential(position)
+                + self.halo.potential(position)
+        }
+
+        /// Acceleration in meters/second^2 at galactocentric position in meters.
+        pub fn acceleration(&self, position: DVec3) -> DVec3 {
+            self.nucleus.acceleration(position)
+                + self.bulge.acceleration(position)
+                + self.disk.acceleration(position)
+                + self.halo.acceleration(position)
+        }
+
+        /// Circular velocity in meters/second in the galactic plane at cylindrical radius in meters.
+        pub fn circular_velocity(&self, radius: f64) -> f64 {
+            let acceleration = self.acceleration(DVec3::new(radius, 0., 0.));
+
+            (-acceleration.x * radius).sqrt()
+        }
+    }
+
+    /// One kick-drift-kick leapfrog step of time_step seconds (negative to go back in time) in potential.
+    /// state is a galactocentric position in meters and velocity in meters/second, output is the state after the step.
+    pub fn leapfrog_step(
+        potential: &MilkyWayPotential,
+        state: (DVec3, DVec3),
+        time_step: f64,
+    ) -> (DVec3, DVec3) {
+        let (position, velocity) = state;
+
+        let half_kick = velocity + (potential.acceleration(position) * time_step / 2.);
+        let position = position + (half_kick * time_step);
+        let velocity = half_kick + (potential.acceleration(position) * time_step / 2.);
+
+        (position, velocity)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn circular_velocity_at_the_sun() {
+            //About 231.8 km/s at 8 kpc for the parameters of gala
+            let velocity = MilkyWayPotential::default().circular_velocity(8. * KPC);
+
+            assert!((velocity - 231761.4).abs() < 1.);
+        }
+
+        #[test]
+        fn acceleration_is_minus_the_gradient_of_the_potential() {
+            let potential = MilkyWayPotential::default();
+            let position = DVec3::new(8., 1., 0.5) * KPC;
+            let h = 1e-4 * KPC;
+
+            let gradient = DVec3::new(
+                potential.potential(position + (DVec3::X * h))
+                    - potential.potential(position - (DVec3::X * h)),
+                potential.potential(position + (DVec3::Y * h))
+                    - potential.potential(position - (DVec3::Y * h)),
+                potential.potential(position + (DVec3::Z * h))
+                    - potential.potential(position - (DVec3::Z * h)),
+            ) / (2. * h);
+            let acceleration = potential.acceleration(position);
+
+            assert!((acceleration + gradient).length() / acceleration.length() < 1e-6);
+        }
+
+        #[test]
+        fn components_are_finite_at_the_center() {
+            let potential = MilkyWayPotential::default();
+
+            assert!(potential.potential(DVec3::ZERO).is_finite());
+            assert_eq!(potential.acceleration(DVec3::ZERO), DVec3::ZERO);
+
+            //The NFW potential tends to -G * mass / scale_radius at the center
+            let halo = potential.halo;
+            let near = halo.potential(DVec3::X * 1e-6 * KPC);
+            assert!((near / halo.potential(DVec3::ZERO) - 1.).abs() < 1e-6);
+        }
+
+        #[test]
+        fn leapfrog_is_time_reversible() {
+            let potential = MilkyWayPotential::default();
+            let state = (
+                DVec3::new(-8.122, 0., 0.0208) * KPC,
+                DVec3::new(12.9e3, -245.6e3, 7.78e3),
+            );
+            let time_step = 1e5 * crate::time::JULIAN_YEAR;
+
+            let forward = leapfrog_step(&potential, state, time_step);
+            let back = leapfrog_step(&potential, forward, -time_step);
+
+            assert!((back.0 - state.0).length() < 1.);
+            assert!((back.1 - state.1).length() < 1e-9);
+        }
+    }
+}
+
+/// Orbits of stars in the Milky Way integrated in a [`super::galactic_potential::MilkyWayPotential`], from galactocentric states
+/// or directly from the heliocentric states of [`super::position::position`] and [`super::velocity::velocity`].
+/// Times are in Julian years relative to the epoch of the state, positions in meters and velocities in meters/second.
+pub mod galactic_orbit {
+    use super::galactic_potential::{leapfrog_step, MilkyWayPotential};
+    use super::galactocentric::{
+        galactocentric_position, galactocentric_velocity, GalactocentricFrame,
+    };
+    use super::time::JULIAN_YEAR;
+    use glam::f64::DVec3;
+
+    /// Integrated galactic orbit, the trajectory sorted in time together with its pericenter, apocenter and z_max in meters and eccentricity.
+    /// Pericenter and apocenter are the smallest and largest galactocentric distances along the trajectory and eccentricity is
+    /// (apocenter - pericenter) / (apocenter + pericenter).
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct GalacticOrbit {
+        pub times: std::vec::Vec<f64>,
+        pub positions: std::vec::Vec<DVec3>,
+        pub velocities: std::vec::Vec<DVec3>,
+        pub pericenter: f64,
+        pub apocenter: f64,
+        pub eccentricity: f64,
+        pub z_max: f64,
+    }
+
+    //States from the epoch of state until time years in steps of time_step years, the first state is excluded
+    fn integrate(
+        state: (DVec3, DVec3),
+        potential: &MilkyWayPotential,
+        time: f64,
+        time_step: f64,
+    ) -> std::vec::Vec<(f64, DVec3, DVec3)> {
+        let steps = (time / time_step).abs().ceil() as usize;
+        let mut states = std::vec::Vec::with_capacity(steps);
+        if steps == 0 {
+            return states;
+        }
+
+        //Adjust the step so that the integration ends exactly at time
+        let step = time / steps as f64;
+        let mut current = state;
+        for index in 1..=steps {
+            current = leapfrog_step(potential, current, step * JULIAN_YEAR);
+            states.push((index as f64 * step, current.0, current.1));
+        }
+
+        states
+    }
+
+    /// Integrates the orbit of a star with galactocentric state (position in meters, velocity in meters/second) in potential
+    /// backward to start and forward to end in years (start <= 0 <= end) with leapfrog steps of at most time_step years.
+    /// Returns `None` if time_step is not positive and finite or if start or end is not finite.
+    pub fn integrate_orbit(
+        state: (DVec3, DVec3),
+        potential: &MilkyWayPotential,
+        start: f64,
+        end: f64,
+        time_step: f64,
+    ) -> Option<GalacticOrbit> {
+        if !(time_step > 0. && time_step.is_finite() && start.is_finite() && end.is_finite()) {
+            return None;
+        }
+
+        let mut trajectory = integrate(state, potential, start.min(0.), time_step);
+        trajectory.reverse();
+        trajectory.push((0., state.0, state.1));
+        trajectory.append(&mut integrate(state, potential, end.max(0.), time_step));
+
+        let mut pericenter = f64::INFINITY;
+        let mut apocenter: f64 = 0.;
+        let mut z_max: f64 = 0.;
+        for (_, position, _) in &trajectory {
+            pericenter = pericenter.min(position.length());
+            apocenter = apocenter.max(position.length());
+            z_max = z_max.max(position.z.abs());
+        }
+
+        Some(GalacticOrbit {
+            times: trajectory.iter().map(|(time, _, _)| *time).collect(),
+            positions: trajectory
+                .iter()
+                .map(|(_, position, _)| *position)
+                .collect(),
+            velocities: trajectory
+                .iter()
+                .map(|(_, _, velocity)| *velocity)
+                .collect(),
+            pericenter,
+            apocenter,
+            eccentricity: (apocenter - pericenter) / (apocenter + pericenter),
+            z_max,
+        })
+    }
+
+    /// Integrates the orbit of a star from its heliocentric position in meters and velocity in meters/second (e.g. from [`super::position::position`]
+    /// and [`super::velocity::velocity`]) after placing it in frame, see [`integrate_orbit`] for the other arguments.
+    pub fn integrate_star_orbit(
+        star: (DVec3, DVec3),
+        potential: &MilkyWayPotential,
+        frame: &GalactocentricFrame,
+        start: f64,
+        end: f64,
+        time_step: f64,
+    ) -> Option<GalacticOrbit> {
+        let state = (
+            galactocentric_position(star.0, frame),
+            galactocentric_velocity(star.1, frame),
+        );
+
+        integrate_orbit(state, potential, start, end, time_step)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::common::PARSEC;
+
+        //Circular orbit at 8 kpc in the galactic plane
+        fn circular_state(potential: &MilkyWayPotential) -> (DVec3, DVec3) {
+            let radius = 8e3 * PARSEC;
+
+            (
+                DVec3::new(radius, 0., 0.),
+                DVec3::new(0., potential.circular_velocity(radius), 0.),
+            )
+        }
+
+        #[test]
+        fn circular_orbit_keeps_its_radius_and_energy() {
+            let potential = MilkyWayPotential::default();
+            let state = circular_state(&potential);
+            let orbit = integrate_orbit(state, &potential, -2.5e8, 2.5e8, 1e5).unwrap();
+
+            assert!(orbit.eccentricity < 1e-5);
+            assert_eq!(orbit.z_max, 0.);
+
+            let energy = |position: DVec3, velocity: DVec3| {
+                potential.potential(position) + (velocity.length_squared() / 2.)
+            };
+            let initial = energy(state.0, state.1);
+            for (position, velocity) in orbit.positions.iter().zip(&orbit.velocities) {
+                assert!((energy(*position, *velocity) / initial - 1.).abs() < 1e-6);
+            }
+        }
+
+        #[test]
+        fn trajectory_is_sorted_and_ends_at_the_window() {
+            let potential = MilkyWayPotential::default();
+            let state = circular_state(&potential);
+            let orbit = integrate_orbit(state, &potential, -1e6, 3e6, 1e6).unwrap();
+
+            assert_eq!(orbit.times, vec![-1e6, 0., 1e6, 2e6, 3e6]);
+            assert_eq!(orbit.positions[1], state.0);
+            assert_eq!(orbit.velocities[1], state.1);
+
+            //Only the epoch of the state if the window is empty
+            let orbit = integrate_orbit(state, &potential, 0., 0., 1e6).unwrap();
+            assert_eq!(orbit.times, vec![0.]);
+        }
+
+        #[test]
+        fn orbit_of_the_sun() {
+            //The sun moves on a mildly eccentric orbit between about 8 and 9.5 kpc and about 0.1 kpc above and below the plane
+            let orbit = integrate_star_orbit(
+                (DVec3::ZERO, DVec3::ZERO),
+                &MilkyWayPotential::default(),
+                &GalactocentricFrame::default(),
+                -5e8,
+                0.,
+                1e5,
+            )
+            .unwrap();
+
+            assert!(orbit.pericenter > 7.5e3 * PARSEC && orbit.pericenter < 8.2e3 * PARSEC);
+            assert!(orbit.apocenter > 8.5e3 * PARSEC && orbit.apocenter < 10e3 * PARSEC);
+            assert!(orbit.z_max > 50. * PARSEC && orbit.z_max < 150. * PARSEC);
+        }
+
+        #[test]
+        fn invalid_time_steps_are_rejected() {
+            let potential = MilkyWayPotential::default();
+            let state = circular_state(&potential);
+
+            for time_step in [0., -1e5, f64::NAN, f64::INFINITY] {
+                assert_eq!(
+                    integrate_orbit(state, &potential, -1e6, 1e6, time_step),
+                    None
+                );
+            }
+            assert_eq!(
+                integrate_orbit(state, &potential, f64::NEG_INFINITY, 1e6, 1e5),
+                None
+            );
+            assert_eq!(
+                integrate_orbit(state, &potential, -1e6, f64::NAN, 1e5),
+                None
+            );
+        }
+    }
+}
+
+/// Time scale helpers used by the epoch dependent parts of `spv-rs`.
+/// Epochs are given as Julian years (e.g. 2000.0 for J2000 and 2016.0 for Gaia DR3) unless stated otherwise.
+pub mod time {
+    use super::coordinate_transforms::mean_obliquity;
+    use super::precession::nutation;
+
+    /// Julian date of the J2000.0 epoch.
+    pub const J2000_JD: f64 = 2451545.0;
+
+    /// Number of days in a Julian year.
+    pub const JULIAN_YEAR_DAYS: f64 = 365.25;
+
+    /// Number of seconds in a Julian year, the year used for every per year quantity in `spv-rs`.
+    pub const JULIAN_YEAR: f64 = 31557600.;
+
+    /// Converts a Julian date to a Julian epoch in years.
+    pub fn julian_date_to_epoch(julian_date: f64) -> f64 {
+        2000. + ((julian_date - J2000_JD) / JULIAN_YEAR_DAYS)
+    }
+
+    /// Converts a Julian epoch in years to a Julian date.
+    pub fn epoch_to_julian_date(epoch: f64) -> f64 {
+        J2000_JD + ((epoch - 2000.) * JULIAN_YEAR_DAYS)
+    }
+
+    /// Julian centuries since J2000.0 for a Julian epoch in years.
+    pub fn julian_centuries(epoch: f64) -> f64 {
+        (epoch - 2000.) / 100.
+    }
+
+    /// Converts a Besselian epoch (e.g. 1950.0 for B1950) to a Julian epoch in years.
+    pub fn besselian_to_julian_epoch(besselian_epoch: f64) -> f64 {
+        julian_date_to_epoch(2415020.31352 + ((besselian_epoch - 1900.) * 365.242198781))
+    }
+
+    /// Converts a Julian epoch in years to a Besselian epoch.
+    pub fn julian_to_besselian_epoch(epoch: f64) -> f64 {
+        1900. + ((epoch_to_julian_date(epoch) - 2415020.31352) / 365.242198781)
+    }
+
+    /// Julian date of a Gregorian calendar date and time of day, in the same time scale as the input (e.g. UTC).
+    /// second can contain a fraction.
+    pub fn julian_date(
+        year: i32,
+        month: u32,
+        day: u32,
+        hour: u32,
+        minute: u32,
+        second: f64,
+    ) -> f64 {
+        let (y, m) = if month <= 2 {
+            (year as f64 - 1., month as f64 + 12.)
+        } else {
+            (year as f64, month as f64)
+        };
+
+        let a = (y / 100.).floor();
+        let b = 2. - a + (a / 4.).floor();
+
+        (365.25 * (y + 4716.)).floor() + (30.6001 * (m + 1.)).floor() + day as f64 + b - 1524.5
+            + ((hour as f64 + (minute as f64 / 60.) + (second / 3600.)) / 24.)
+    }
+
+    //UTC dates (year, month) from which TAI - UTC changed to the value in seconds
+    const LEAP_SECONDS: [(i32, u32, f64); 28] = [
+        (1972, 1, 10.),
+        (1972, 7, 11.),
+        (1973, 1, 12.),
+        (1974, 1, 13.),
+        (1975, 1, 14.),
+        (1976, 1, 15.),
+        (1977, 1, 16.),
+        (1978, 1, 17.),
+        (1979, 1, 18.),
+        (1980, 1, 19.),
+        (1981, 7, 20.),
+        (1982, 7, 21.),
+        (1983, 7, 22.),
+        (1985, 7, 23.),
+        (1988, 1, 24.),
+        (1990, 1, 25.),
+        (1991, 1, 26.),
+        (1992, 7, 27.),
+        (1993, 7, 28.),
+        (1994, 7, 29.),
+        (1996, 1, 30.),
+        (1997, 7, 31.),
+        (1999, 1, 32.),
+        (2006, 1, 33.),
+        (2009, 1, 34.),
+        (2012, 7, 35.),
+        (2015, 7, 36.),
+        (2017, 1, 37.),
+    ];
+
+    /// TT - UTC in seconds for a Julian date in UTC, from the table of leap seconds (TT = TAI + 32.184 s).
+    /// Dates before 1972 use the 1972 value and dates after the last known leap second use the latest value.
+    pub fn tt_minus_utc(julian_date_utc: f64) -> f64 {
+        let mut tai_minus_utc = LEAP_SECONDS[0].2;
+        for (year, month, seconds) in LEAP_SECONDS {
+            if julian_date_utc >= julian_date(year, month, 1, 0, 0, 0.) {
+                tai_minus_utc = seconds;
+            }
+        }
+
+        tai_minus_utc + 32.184
+    }
+
+    /// Converts a Julian date in UTC to a Julian date in TT.
+    pub fn utc_to_tt(julian_date_utc: f64) -> f64 {
+        julian_date_utc + (tt_minus_utc(julian_date_utc) / 86400.)
+    }
+
+    /// Earth rotation angle in degrees (IAU 2000) for a Julian date in UT1, UTC can be used instead at the level of a second.
+    pub fn earth_rotation_angle(julian_date_ut1: f64) -> f64 {
+        let days = julian_date_ut1 - J2000_JD;
+
+        (360. * (0.779057273264 + (0.00273781191135448 * days) + days.fract())).rem_euclid(360.)
+    }
+
+    /// Greenwich mean sidereal time in degrees (IAU 2006) for a Julian date in UT1, UTC can be used instead at the level of a second.
+    pub fn greenwich_mean_sidereal_time(julian_date_ut1: f64) -> f64 {
+        let t = (utc_to_tt(julian_date_ut1) - J2000_JD) / 36525.;
+
+        //Polynomial part in arcseconds
+        let polynomial = 0.014506 + (4612.156534 * t) + (1.3915817 * t.powf(2.))
+            - (0.00000044 * t.powf(3.))
+            - (0.000029956 * t.powf(4.))
+            - (0.0000000368 * t.powf(5.));
+
+        (earth_rotation_angle(julian_date_ut1) + (polynomial / 3600.)).rem_euclid(360.)
+    }
+
+    /// Greenwich apparent sidereal time in degrees for a Julian date in UT1, the mean sidereal time plus the equation of the equinoxes.
+    pub fn greenwich_apparent_sidereal_time(julian_date_ut1: f64) -> f64 {
+        let epoch = julian_date_to_epoch(utc_to_tt(julian_date_ut1));
+        let equation_of_the_equinoxes =
+            nutation(epoch).x * mean_obliquity(epoch).to_radians().cos() / 3600.;
+
+        (greenwich_mean_sidereal_time(julian_date_ut1) + equation_of_the_equinoxes).rem_euclid(360.)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn julian_dates_of_known_instants() {
+            assert_eq!(julian_date(2000, 1, 1, 12, 0, 0.), J2000_JD);
+            //Meeus, Astronomical Algorithms, examples 7.a and 12.a
+            assert!((julian_date(1957, 10, 4, 19, 26, 24.) - 2436116.31).abs() < 1e-8);
+            assert_eq!(julian_date(1987, 4, 10, 0, 0, 0.), 2446895.5);
+            assert!((julian_date_to_epoch(epoch_to_julian_date(2016.5)) - 2016.5).abs() < 1e-12);
+        }
+
+        #[test]
+        fn leap_seconds() {
+            assert_eq!(tt_minus_utc(julian_date(2016, 12, 31, 23, 0, 0.)), 68.184);
+            assert_eq!(tt_minus_utc(julian_date(2017, 1, 1, 0, 0, 0.)), 69.184);
+            assert_eq!(tt_minus_utc(julian_date(1960, 1, 1, 0, 0, 0.)), 42.184);
+        }
+
+        #[test]
+        fn earth_rotation_angle_matches_sofa() {
+            //SOFA iauEra00 at MJD 54388.0
+            let era = earth_rotation_angle(2400000.5 + 54388.0).to_radians();
+            assert!((era - 0.4022837240028158).abs() < 1e-12);
+        }
+
+        #[test]
+        fn greenwich_mean_sidereal_time_matches_sofa() {
+            //SOFA iauGmst06 at MJD 53736.0 with TT = UT1, here TT is derived from UTC which moves the result by about 5e-10 rad
+            let gmst = greenwich_mean_sidereal_time(2400000.5 + 53736.0).to_radians();
+            assert!((gmst - 1.7541749718700912).abs() < 1e-9);
+        }
+
+        #[test]
+        fn greenwich_apparent_sidereal_time_of_meeus() {
+            //Meeus, Astronomical Algorithms, example 12.a, 13h10m46.1351s at 1987 April 10 0h UT
+            let gast = greenwich_apparent_sidereal_time(2446895.5);
+            assert!((gast / 15. * 3600. - 47446.1351).abs() < 0.01);
+        }
+    }
+}
+
+/// Precession (IAU 2006) and nutation (IAU 2000B) between the ICRS and the mean or true equator and equinox of date.
+/// Used to bring historical measurements that are referred to the equinox of date onto J2000/ICRS.
+/// epoch is the date in Julian years (TT), see [`super::time`] for conversions.
+pub mod precession {
+    use super::coordinate_transforms::{
+        equatorial_to_ecliptic_of_date, mean_obliquity, spherical_angles, unit_vector,
+    };
+    use super::time::julian_centuries;
+    use glam::f64::{DMat3, DVec2, DVec3};
+
+    const ARCSEC_TO_RAD: f64 = std::f64::consts::PI / (180. * 3600.);
+
+    //Luni-solar nutation terms of IAU 2000B: multipliers of l, l', F, D and Omega followed by
+    //the longitude coefficients (sin, sin*t, cos) and the obliquity coefficients (cos, cos*t, sin) in 0.1 microarcseconds
+    #[rustfmt::skip]
+    const NUTATION_2000B: [([f64; 5], [f64; 6]); 77] = [
+        ([0., 0., 0., 0., 1.], [-172064161., -174666., 33386., 92052331., 9086., 15377.]),
+        ([0., 0., 2., -2., 2.], [-13170906., -1675., -13696., 5730336., -3015., -4587.]),
+        ([0., 0., 2., 0., 2.], [-2276413., -234., 2796., 978459., -485., 1374.]),
+        ([0., 0., 0., 0., 2.], [2074554., 207., -698., -897492., 470., -291.]),
+        ([0., 1., 0., 0., 0.], [1475877., -3633., 11817., 73871., -184., -1924.]),
+        ([0., 1., 2., -2., 2.], [-516821., 1226., -524., 224386., -677., -174.]),
+        ([1., 0., 0., 0., 0.], [711159., 73., -872., -6750., 0., 358.]),
+        ([0., 0., 2., 0., 1.], [-387298., -367., 380., 200728., 18., 318.]),
+        ([1., 0., 2., 0., 2.], [-301461., -36., 816., 129025., -63., 367.]),
+        ([0., -1., 2., -2., 2.], [215829., -494., 111., -95929., 299., 132.]),
+        ([0., 0., 2., -2., 1.], [128227., 137., 181., -68982., -9., 39.]),
+        ([-1., 0., 2., 0., 2.], [123457., 11., 19., -53311., 32., -4.]),
+        ([-1., 0., 0., 2., 0.], [156994., 10., -168., -1235., 0., 82.]),
+        ([1., 0., 0., 0., 1.], [63110., 63., 27., -33228., 0., -9.]),
+        ([-1., 0., 0., 0., 1.], [-57976., -63., -189., 31429., 0., -75.]),
+        ([-1., 0., 2., 2., 2.], [-59641., -11., 149., 25543., -11., 66.]),
+        ([1., 0., 2., 0., 1.], [-51613., -42., 129., 26366., 0., 78.]),
+        ([-2., 0., 2., 0., 1.], [45893., 50., 31., -24236., -10., 20.]),
+        ([0., 0., 0., 2., 0.], [63384., 11., -150., -1220., 0., 29.]),
+        ([0., 0., 2., 2., 2.], [-38571., -1., 158., 16452., -11., 68.]),
+        ([0., -2., 2., -2., 2.], [32481., 0., 0., -13870., 0., 0.]),
+        ([-2., 0., 0., 2., 0.], [-47722., 0., -18., 477., 0., -25.]),
+        ([2., 0., 2., 0., 2.], [-31046., -1., 131., 13238., -11., 59.]),
+        ([1., 0., 2., -2., 2.], [28593., 0., -1., -12338., 10., -3.]),
+        ([-1., 0., 2., 0., 1.], [20441., 21., 10., -10758., 0., -3.]),
+        ([2., 0., 0., 0., 0.], [29243., 0., -74., -609., 0., 13.]),
+        ([0., 0., 2., 0., 0.], [25887., 0., -66., -550., 0., 11.]),
+        ([0., 1., 0., 0., 1.], [-14053., -25., 79., 8551., -2., -45.]),
+        ([-1., 0., 0., 2., 1.], [15164., 10., 11., -8001., 0., -1.]),
+        ([0., 2., 2., -2., 2.], [-15794., 72., -16., 6850., -42., -5.]),
+        ([0., 0., -2., 2., 0.], [21783., 0., 13., -167., 0., 13.]),
+        ([1., 0., 0., -2., 1.], [-12873., -10., -37., 6953., 0., -14.]),
+        ([0., -1., 0., 0., 1.], [-12654., 11., 63., 6415., 0., 26.]),
+        ([-1., 0., 2., 2., 1.], [-10204., 0., 25., 5222., 0., 15.]),
+        ([0., 2., 0., 0., 0.], [16707., -85., -10., 168., -1., 10.]),
+        ([1., 0., 2., 2., 2.], [-7691., 0., 44., 3268., 0., 19.]),
+        ([-2., 0., 2., 0., 0.], [-11024., 0., -14., 104., 0., 2.]),
+        ([0., 1., 2., 0., 2.], [7566., -21., -11., -3250., 0., -5.]),
+        ([0., 0., 2., 2., 1.], [-6637., -11., 25., 3353., 0., 14.]),
+        ([0., -1., 2., 0., 2.], [-7141., 21., 8., 3070., 0., 4.]),
+        ([0., 0., 0., 2., 1.], [-6302., -11., 2., 3272., 0., 4.]),
+        ([1., 0., 2., -2., 1.], [5800., 10., 2., -3045., 0., -1.]),
+        ([2., 0., 2., -2., 2.], [6443., 0., -7., -2768., 0., -4.]),
+        ([-2., 0., 0., 2., 1.], [-5774., -11., -15., 3041., 0., -5.]),
+        ([2., 0., 2., 0., 1.], [-5350., 0., 21., 2695., 0., 12.]),
+        ([0., -1., 2., -2., 1.], [-4752., -11., -3., 2719., 0., -3.]),
+        ([0., 0., 0., -2., 1.], [-4940., -11., -21., 2720., 0., -9.]),
+        ([-1., -1., 0., 2., 0.], [7350., 0., -8., -51., 0., 4.]),
+        ([2., 0., 0., -2., 1.], [4065., 0., 6., -2206., 0., 1.]),
+        ([1., 0., 0., 2., 0.], [6579., 0., -24., -199., 0., 2.]),
+        ([0., 1., 2., -2., 1.], [3579., 0., 5., -1900., 0., 1.]),
+        ([1., -1., 0., 0., 0.], [4725., 0., -6., -41., 0., 3.]),
+        ([-2., 0., 2., 0., 2.], [-3075., 0., -2., 1313., 0., -1.]),
+        ([3., 0., 2., 0., 2.], [-2904., 0., 15., 1233., 0., 7.]),
+        ([0., -1., 0., 2., 0.], [4348., 0., -10., -81., 0., 2.]),
+        ([1., -1., 2., 0., 2.], [-2878., 0., 8., 1232., 0., 4.]),
+        ([0., 0., 0., 1., 0.], [-4230., 0., 5., -20., 0., -2.]),
+        ([-1., -1., 2., 2., 2.], [-2819., 0., 7., 1207., 0., 3.]),
+        ([-1., 0., 2., 0., 0.], [-4056., 0., 5., 40., 0., -2.]),
+        ([0., -1., 2., 2., 2.], [-2647., 0., 11., 1129., 0., 5.]),
+        ([-2., 0., 0., 0., 1.], [-2294., 0., -10., 1266., 0., -4.]),
+        ([1., 1., 2., 0., 2.], [2481., 0., -7., -1062., 0., -3.]),
+        ([2., 0., 0., 0., 1.], [2179., 0., -2., -1129., 0., -2.]),
+        ([-1., 1., 0., 1., 0.], [3276., 0., 1., -9., 0., 0.]),
+        ([1., 1., 0., 0., 0.], [-3389., 0., 5., 35., 0., -2.]),
+        ([1., 0., 2., 0., 0.], [3339., 0., -13., -107., 0., 1.]),
+        ([-1., 0., 2., -2., 1.], [-1987., 0., -6., 1073., 0., -2.]),
+        ([1., 0., 0., 0., 2.], [-1981., 0., 0., 854., 0., 0.]),
+        ([-1., 0., 0., 1., 0.], [4026., 0., -353., -553., 0., -139.]),
+        ([0., 0., 2., 1., 2.], [1660., 0., -5., -710., 0., -2.]),
+        ([-1., 0., 2., 4., 2.], [-1521., 0., 9., 647., 0., 4.]),
+        ([-1., 1., 0., 1., 1.], [1314., 0., 0., -700., 0., 0.]),
+        ([0., -2., 2., -2., 1.], [-1283., 0., 0., 672., 0., 0.]),
+        ([1., 0., 2., 2., 1.], [-1331., 0., 8., 663., 0., 4.]),
+        ([-2., 0., 2., 2., 2.], [1383., 0., -2., -594., 0., -2.]),
+        ([-1., 0., 0., 0., 2.], [1405., 0., 4., -610., 0., 2.]),
+        ([1., 1., 2., -2., 2.], [1290., 0., 0., -556., 0., 0.]),
+    ];
+
+    //Frame rotation around the x-axis by angle in radians
+    fn rotation_x(angle: f64) -> DMat3 {
+        DMat3::from_rotation_x(-angle)
+    }
+
+    //Frame rotation around the z-axis by angle in radians
+    fn rotation_z(angle: f64) -> DMat3 {
+        DMat3::from_rotation_z(-angle)
+    }
+
+    //Rotation matrix from the four Fukushima-Williams angles in radians
+    fn fukushima_williams_matrix(gamma: f64, phi: f64, psi: f64, epsilon: f64) -> DMat3 {
+        rotation_x(-epsilon) * rotation_z(-psi) * rotation_x(phi) * rotation_z(gamma)
+    }
+
+    /// Fukushima-Williams precession angles gamma_bar, phi_bar and psi_bar in arcseconds (IAU 2006) including the frame bias.
+    /// Output is a 3-dimensional vector with gamma_bar, phi_bar and psi_bar in that order.
+    pub fn precession_angles(epoch: f64) -> DVec3 {
+        let t = julian_centuries(epoch);
+
+        let gamma = -0.052928
+            + (10.556378
+                + (0.4932044 + (-0.00031238 + (-0.000002788 + (0.000000026 * t)) * t) * t) * t)
+                * t;
+        let phi = 84381.412819
+            + (-46.811016
+                + (0.0511268 + (0.00053289 + (-0.00000044 + (-0.0000000176 * t)) * t) * t) * t)
+                * t;
+        let psi = -0.041775
+            + (5038.481484
+                + (1.5584175 + (-0.00018522 + (-0.000026452 + (-0.0000000148 * t)) * t) * t) * t)
+                * t;
+
+        DVec3::new(gamma, phi, psi)
+    }
+
+    /// Nutation in longitude and obliquity in arcseconds (IAU 2000B).
+    /// Output is a 2-dimensional vector with delta psi and delta epsilon in that order.
+    pub fn nutation(epoch: f64) -> DVec2 {
+        let t = julian_centuries(epoch);
+        let turn = 1296000.;
+
+        //Fundamental arguments (Simon et al. 1994) in radians
+        let arguments = [
+            ((485868.249036 + (1717915923.2178 * t)) % turn) * ARCSEC_TO_RAD,
+            ((1287104.79305 + (129596581.0481 * t)) % turn) * ARCSEC_TO_RAD,
+            ((335779.526232 + (1739527262.8478 * t)) % turn) * ARCSEC_TO_RAD,
+            ((1072260.70369 + (1602961601.209 * t)) % turn) * ARCSEC_TO_RAD,
+            ((450160.398036 - (6962890.5431 * t)) % turn) * ARCSEC_TO_RAD,
+        ];
+
+        let mut delta_psi = 0.;
+        let mut delta_epsilon = 0.;
+        for (multipliers, coefficients) in NUTATION_2000B.iter().rev() {
+            let argument: f64 = multipliers
+                .iter()
+                .zip(arguments.iter())
+                .map(|(n, a)| n * a)
+                .sum();
+            let (sin_arg, cos_arg) = argument.sin_cos();
+
+            delta_psi +=
+                ((coefficients[0] + (coefficients[1] * t)) * sin_arg) + (coefficients[2] * cos_arg);
+            delta_epsilon +=
+                ((coefficients[3] + (coefficients[4] * t)) * cos_arg) + (coefficients[5] * sin_arg);
+        }
+
+        //From 0.1 microarcseconds to arcseconds plus the fixed offset replacing the planetary terms
+        DVec2::new(
+            (delta_psi / 10000000.) - 0.000135,
+            (delta_epsilon / 10000000.) + 0.000388,
+        )
+    }
+
+    /// Rotation matrix from the ICRS to the mean equator and equinox of date, frame bias and precession (IAU 2006).
+    pub fn precession_matrix(epoch: f64) -> DMat3 {
+        let angles = precession_angles(epoch) * ARCSEC_TO_RAD;
+        let epsilon = mean_obliquity(epoch).to_radians();
+
+        fukushima_williams_matrix(angles.x, angles.y, angles.z, epsilon)
+    }
+
+    /// Rotation matrix from the ICRS to the true equator and equinox of date, frame bias, precession (IAU 2006) and nutation (IAU 2000B).
+    pub fn precession_nutation_matrix(epoch: f64) -> DMat3 {
+        let angles = precession_angles(epoch) * ARCSEC_TO_RAD;
+        let epsilon = mean_obliquity(epoch).to_radians();
+        let nutation = nutation(epoch) * ARCSEC_TO_RAD;
+
+        fukushima_williams_matrix(
+            angles.x,
+            angles.y,
+            angles.z + nutation.x,
+            epsilon + nutation.y,
+        )
+    }
+
+    /// Rotation matrix from the mean equator and equinox of date to the true equator and equinox of date (IAU 2000B).
+    pub fn nutation_matrix(epoch: f64) -> DMat3 {
+        precession_nutation_matrix(epoch) * precession_matrix(epoch).transpose()
+    }
+
+    /// Rotation matrix from the mean equator and equinox of epoch to the mean equator and equinox of target_epoch.
+    pub fn equinox_matrix(epoch: f64, target_epoch: f64) -> DMat3 {
+        precession_matrix(target_epoch) * precession_matrix(epoch).transpose()
+    }
+
+    /// Rotates a position or velocity vector from the ICRS to the mean equator and equinox of date, units are kept as is.
+    pub fn icrs_to_mean_of_date(vector: DVec3, epoch: f64) -> DVec3 {
+        precession_matrix(epoch) * vector
+    }
+
+    /// Rotates a position or velocity vector from the mean equator and equinox of date to the ICRS, units are kept as is.
+    pub fn mean_of_date_to_icrs(vector: DVec3, epoch: f64) -> DVec3 {
+        precession_matrix(epoch).transpose() * vector
+    }
+
+    /// Rotates a position or velocity vector from the ICRS to the true equator and equinox of date, units are kept as is.
+    pub fn icrs_to_true_of_date(vector: DVec3, epoch: f64) -> DVec3 {
+        precession_nutation_matrix(epoch) * vector
+    }
+
+    /// Rotates a position or velocity vector from the true equator and equinox of date to the ICRS, units are kept as is.
+    pub fn true_of_date_to_icrs(vector: DVec3, epoch: f64) -> DVec3 {
+        precession_nutation_matrix(epoch).transpose() * vector
+    }
+
+    /// Rotates a position or velocity vector from the ICRS to the mean ecliptic and equinox of date, units are kept as is.
+    pub fn icrs_to_ecliptic_of_date(vector: DVec3, epoch: f64) -> DVec3 {
+        equatorial_to_ecliptic_of_date(icrs_to_mean_of_date(vector, epoch), epoch)
+    }
+
+    /// Converts right_ascension and declination in degrees referred to the mean equator and equinox of date to ICRS right ascension and declination in degrees.
+    /// Output is a 2-dimensional vector with right ascension and declination in that order.
+    pub fn mean_of_date_to_icrs_angles(
+        right_ascension: f64,
+        declination: f64,
+        epoch: f64,
+    ) -> DVec2 {
+        spherical_angles(mean_of_date_to_icrs(
+            unit_vector(right_ascension, declination),
+            epoch,
+        ))
+    }
+
+    /// Converts ICRS right_ascension and declination in degrees to the mean equator and equinox of date.
+    /// Output is a 2-dimensional vector with right ascension and declination in that order.
+    pub fn icrs_to_mean_of_date_angles(
+        right_ascension: f64,
+        declination: f64,
+        epoch: f64,
+    ) -> DVec2 {
+        spherical_angles(icrs_to_mean_of_date(
+            unit_vector(right_ascension, declination),
+            epoch,
+        ))
+    }
+
+    /// Converts right_ascension and declination in degrees referred to the true equator and equinox of date to ICRS right ascension and declination in degrees.
+    /// Output is a 2-dimensional vector with right ascension and declination in that order.
+    pub fn true_of_date_to_icrs_angles(
+        right_ascension: f64,
+        declination: f64,
+        epoch: f64,
+    ) -> DVec2 {
+        spherical_angles(true_of_date_to_icrs(
+            unit_vector(right_ascension, declination),
+            epoch,
+        ))
+    }
+
+    /// Converts ICRS right_ascension and declination in degrees to the true equator and equinox of date.
+    /// Output is a 2-dimensional vector with right ascension and declination in that order.
+    pub fn icrs_to_true_of_date_angles(
+        right_ascension: f64,
+        declination: f64,
+        epoch: f64,
+    ) -> DVec2 {
+        spherical_angles(icrs_to_true_of_date(
+            unit_vector(right_ascension, declination),
+            epoch,
+        ))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn epoch(modified_julian_date: f64) -> f64 {
+            2000. + ((2400000.5 + modified_julian_date - 2451545.) / 365.25)
+        }
+
+        #[test]
+        fn precession_matrix_matches_sofa() {
+            //SOFA iauPmat06 at MJD 50123.9999, rows of the bias-precession matrix
+            let expected = [
+                [
+                    0.9999995505176007,
+                    0.0008695404617348209,
+                    0.0003779735201865589,
+                ],
+                [
+                    -0.0008695404723772031,
+                    0.9999996219496027,
+                    -1.3617524970802702e-7,
+                ],
+                [
+                    -0.00037797349570340897,
+                    -1.924880847894457e-7,
+                    0.9999999285679972,
+                ],
+            ];
+            let matrix = precession_matrix(epoch(50123.9999));
+
+            for (row, expected_row) in expected.iter().enumerate() {
+                for (collum, value) in expected_row.iter().enumerate() {
+                    assert!((matrix.col(collum)[row] - value).abs() < 1e-12);
+                }
+            }
+        }
+
+        #[test]
+        fn nutation_matches_sofa() {
+            //SOFA iauNut00b at MJD 53736.0 in radians
+            let nutation = nutation(epoch(53736.0)) * ARCSEC_TO_RAD;
+
+            assert!((nutation.x - -9.632552291148363e-6).abs() < 1e-13);
+            assert!((nutation.y - 4.063197106621159e-5).abs() < 1e-13);
+        }
+
+        #[test]
+        fn rotations_are_orthogonal_and_invertible() {
+            let matrix = precession_nutation_matrix(2030.5);
+            assert!((matrix * matrix.transpose()).abs_diff_eq(DMat3::IDENTITY, 1e-15));
+
+            let there_and_back = equinox_matrix(1950., 2000.) * equinox_matrix(2000., 1950.);
+            assert!(there_and_back.abs_diff_eq(DMat3::IDENTITY, 1e-15));
+
+            let angles = icrs_to_true_of_date_angles(10.684708, 41.26875, 2024.);
+            let back = true_of_date_to_icrs_angles(angles.x, angles.y, 2024.);
+            assert!((back - DVec2::new(10.684708, 41.26875)).length() < 1e-10);
+        }
+
+        #[test]
+        fn general_precession_in_longitude() {
+            //About 50.3 arcseconds per year along the ecliptic
+            let start = icrs_to_ecliptic_of_date(DVec3::X, 2000.);
+            let end = icrs_to_ecliptic_of_date(DVec3::X, 2100.);
+            let rate = (spherical_angles(start).x - spherical_angles(end).x) * 3600. / 100.;
+
+            assert!((rate.abs() - 50.29).abs() < 0.05);
+        }
+    }
+}
+
+/// Conversion of B1950 FK4 catalog positions and proper motions to J2000 FK5, which is close enough to the ICRS for use with
+/// [`super::position::position`] and [`super::velocity::velocity`].
+/// The method follows Standish (1982) and the Explanatory Supplement to the Astronomical Almanac (1992, Sect. 3.591), including the removal of the elliptic aberration E-terms.
+pub mod fk4 {
+    use super::astrometry::AstrometricParameters;
+    use super::coordinate_transforms::{spherical_angles, unit_vector};
+    use glam::f64::{DVec2, DVec3};
+
+    /// km/s to au per tropical century.
+    const KM_S_TO_AU_PER_CENTURY: f64 = 21.095;
+
+    //E-terms of aberration vector and its rate of change (per tropical century) in radians
+    const E_TERMS: [[f64; 3]; 2] = [
+        [-1.62557e-6, -0.31919e-6, -0.13843e-6],
+        [1.245e-3, -1.580e-3, -0.659e-3],
+    ];
+
+    //6x6 FK4 to FK5 matrix in position and velocity blocks, row by row
+    const FK4_TO_FK5: [[[[f64; 3]; 2]; 3]; 2] = [
+        [
+            [
+                [0.9999256782, -0.0111820611, -0.0048579477],
+                [0.00000242395018, -0.00000002710663, -0.00000001177656],
+            ],
+            [
+                [0.011182061, 0.9999374784, -0.0000271765],
+                [0.00000002710663, 0.00000242397878, -0.00000000006587],
+            ],
+            [
+                [0.0048579479, -0.0000271474, 0.9999881997],
+                [0.00000001177656, -0.00000000006582, 0.00000242410173],
+            ],
+        ],
+        [
+            [
+                [-0.000551, -0.238565, 0.435739],
+                [0.99994704, -0.01118251, -0.00485767],
+            ],
+            [
+                [0.238514, -0.002667, -0.008541],
+                [0.01118251, 0.99995883, -0.00002718],
+            ],
+            [
+                [-0.435623, 0.012254, 0.002117],
+                [0.00485767, -0.00002714, 1.00000956],
+            ],
+        ],
+    ];
+
+    /// Removes the E-terms of aberration from a FK4 right_ascension and declination in degrees.
+    /// Output is a 2-dimensional vector with right ascension and declination in that order in degrees.
+    pub fn remove_e_terms(right_ascension: f64, declination: f64) -> DVec2 {
+        let r = unit_vector(right_ascension, declination);
+        let a = DVec3::from(E_TERMS[0]);
+
+        spherical_angles(r - a + (r * r.dot(a)))
+    }
+
+    /// Adds the E-terms of aberration to a right_ascension and declination in degrees, the inverse of [`remove_e_terms`].
+    /// Output is a 2-dimensional vector with right ascension and declination in that order in degrees.
+    pub fn add_e_terms(right_ascension: f64, declination: f64) -> DVec2 {
+        let r = unit_vector(right_ascension, declination);
+        let a = DVec3::from(E_TERMS[0]);
+
+        //Iterate since the E-terms depend on the direction they are applied to
+        let mut r_fk4 = r;
+        for _i in 0..3 {
+            r_fk4 = (r + a - (r_fk4 * r_fk4.dot(a))).normalize();
+        }
+
+        spherical_angles(r_fk4)
+    }
+
+    /// Converts astrometric parameters in the B1950 FK4 system at epoch B1950.0 to the J2000 FK5 system at epoch J2000.0.
+    /// Units are the same as in [`AstrometricParameters`] except that the FK4 proper motions are per tropical year as in the old catalogs,
+    /// the FK5 output proper motions are per Julian year. If the parallax is zero the radial velocity is passed through unchanged.
+    pub fn fk4_to_fk5(astrometry: AstrometricParameters) -> AstrometricParameters {
+        let parallax_as = astrometry.parallax / 1000.;
+
+        //Position and velocity (arcseconds per tropical century) as a pv-vector
+        let pv = spherical_to_pv(
+            astrometry.right_ascension.to_radians(),
+            astrometry.declination.to_radians(),
+            astrometry.proper_motion_ra * 100.,
+            astrometry.proper_motion_dec * 100.,
+            parallax_as * KM_S_TO_AU_PER_CENTURY * astrometry.radial_velocity,
+        );
+
+        //Remove the E-terms
+        let mut pv_e = [DVec3::ZERO; 2];
+        for (i, row) in pv_e.iter_mut().enumerate() {
+            let a = DVec3::from(E_TERMS[i]);
+            *row = pv[i] - a + (pv[0] * pv[0].dot(a));
+        }
+
+        //Rotate to FK5
+        let mut pv_fk5 = [DVec3::ZERO; 2];
+        for (i, row) in pv_fk5.iter_mut().enumerate() {
+            *row = DVec3::new(
+                (DVec3::from(FK4_TO_FK5[i][0][0]).dot(pv_e[0]))
+                    + (DVec3::from(FK4_TO_FK5[i][0][1]).dot(pv_e[1])),
+                (DVec3::from(FK4_TO_FK5[i][1][0]).dot(pv_e[0]))
+                    + (DVec3::from(FK4_TO_FK5[i][1][1]).dot(pv_e[1])),
+                (DVec3::from(FK4_TO_FK5[i][2][0]).dot(pv_e[0]))
+                    + (DVec3::from(FK4_TO_FK5[i][2][1]).dot(pv_e[1])),
+            );
+        }
+
+        let (right_ascension, declination, r, ra_dot, dec_dot, r_dot) =
+            pv_to_spherical(pv_fk5[0], pv_fk5[1]);
+
+        let (parallax, radial_velocity) = if parallax_as > 1e-30 {
+            (
+                (parallax_as / r) * 1000.,
+                r_dot / (parallax_as * KM_S_TO_AU_PER_CENTURY),
+            )
+        } else {
+            (astrometry.parallax, astrometry.radial_velocity)
+        };
+
+        AstrometricParameters {
+            parallax,
+            right_ascension: right_ascension.to_degrees().rem_euclid(360.),
+            declination: declination.to_degrees(),
+            proper_motion_ra: ra_dot / 100.,
+            proper_motion_dec: dec_dot / 100.,
+            radial_velocity,
+        }
+    }
+
+    //Unit distance pv-vector from spherical angles in radians and their rates
+    fn spherical_to_pv(
+        theta: f64,
+        phi: f64,
+        theta_dot: f64,
+        phi_dot: f64,
+        r_dot: f64,
+    ) -> [DVec3; 2] {
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let (sin_phi, cos_phi) = phi.sin_cos();
+
+        let x = cos_phi * cos_theta;
+        let y = cos_phi * sin_theta;
+        let w = (phi_dot * sin_phi) - (cos_phi * r_dot);
+
+        [
+            DVec3::new(x, y, sin_phi),
+            DVec3::new(
+                (-y * theta_dot) - (w * cos_theta),
+                (x * theta_dot) - (w * sin_theta),
+                (phi_dot * cos_phi) + (sin_phi * r_dot),
+            ),
+        ]
+    }
+
+    //Spherical angles in radians, distance and their rates from a pv-vector
+    fn pv_to_spherical(position: DVec3, velocity: DVec3) -> (f64, f64, f64, f64, f64, f64) {
+        let rxy2 = position.x.powf(2.) + position.y.powf(2.);
+        let r2 = rxy2 + position.z.powf(2.);
+        let r = r2.sqrt();
+        let rxy = rxy2.sqrt();
+        let xyp = (position.x * velocity.x) + (position.y * velocity.y);
+
+        let (theta, phi, theta_dot, phi_dot) = if rxy2 != 0. {
+            (
+                position.y.atan2(position.x),
+                position.z.atan2(rxy),
+                ((position.x * velocity.y) - (position.y * velocity.x)) / rxy2,
+                ((velocity.z * rxy2) - (position.z * xyp)) / (r2 * rxy),
+            )
+        } else {
+            (0., position.z.atan2(rxy), 0., 0.)
+        };
+
+        let r_dot = if r != 0. {
+            (xyp + (position.z * velocity.z)) / r
+        } else {
+            0.
+        };
+
+        (theta, phi, r, theta_dot, phi_dot, r_dot)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn fk4_to_fk5_matches_sofa() {
+            //SOFA iauFk425 test case, angles in radians and proper motions in radians per year
+            let rad_to_as = 1f64.to_degrees() * 3600.;
+            let fk4 = AstrometricParameters::new(
+                134.,
+                0.07626899753879587_f64.to_degrees(),
+                -1.1374053783996059_f64.to_degrees(),
+                1.9737492178490876e-5 * rad_to_as,
+                5.659714913272723e-6 * rad_to_as,
+                8.7,
+            );
+
+            let fk5 = fk4_to_fk5(fk4);
+
+            assert!((fk5.right_ascension.to_radians() - 0.08757989933556445).abs() < 1e-14);
+            assert!((fk5.declination.to_radians() - -1.132279113042092).abs() < 1e-12);
+            assert!((fk5.proper_motion_ra / rad_to_as - 1.953670614474396e-5).abs() < 1e-17);
+            assert!((fk5.proper_motion_dec / rad_to_as - 5.63768667865964e-6).abs() < 1e-18);
+            assert!((fk5.parallax / 1000. - 0.13399199505827677).abs() < 1e-13);
+            assert!((fk5.radial_velocity - 8.736999669183529).abs() < 1e-11);
+        }
+
+        #[test]
+        fn e_terms_round_trip() {
+            let removed = remove_e_terms(150., 20.);
+            let added = add_e_terms(removed.x, removed.y);
+
+            assert!((added - DVec2::new(150., 20.)).length() < 1e-12);
+            //The E-terms are at most about 0.34 arcseconds
+            assert!((removed - DVec2::new(150., 20.)).length() * 3600. < 0.5);
+        }
+    }
+}
+
+/// Linear propagation of astrometric uncertainties to the cartesian position and velocity given by
+/// [`super::position::position`] and [`super::velocity::velocity`].
+/// Covariance matrices are 6x6 and ordered as the fields of [`AstrometricParameters`]: parallax, right ascension, declination,
+/// proper motion in right ascension, proper motion in declination and radial velocity, all in the same units as those fields.
+pub mod uncertainty {
+    use super::astrometry::{normal_triad, AstrometricParameters};
+    use super::common::PARSEC;
+    use super::position::position;
+    use super::time::JULIAN_YEAR;
+    use super::velocity::velocity;
+    use glam::f64::DVec3;
+
+    /// 6x6 matrix stored row by row.
+    pub type Matrix6 = [[f64; 6]; 6];
+
+    /// Cartesian position in meters and velocity in meters/second together with their 6x6 covariance,
+    /// ordered x, y, z, v_x, v_y and v_z in meters and meters/second.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct StateWithCovariance {
+        pub position: DVec3,
+        pub velocity: DVec3,
+        pub covariance: Matrix6,
+    }
+
+    impl StateWithCovariance {
+        /// Standard errors (square root of the diagonal) of x, y, z, v_x, v_y and v_z in meters and meters/second.
+        pub fn standard_errors(&self) -> [f64; 6] {
+            standard_errors(&self.covariance)
+        }
+    }
+
+    /// Builds a covariance matrix from standard errors and a matrix of correlation coefficients.
+    pub fn covariance_from_errors(errors: [f64; 6], correlations: &Matrix6) -> Matrix6 {
+        let mut covariance = [[0.; 6]; 6];
+        for (i, row) in covariance.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                let correlation = if i == j { 1. } else { correlations[i][j] };
+                *value = correlation * errors[i] * errors[j];
+            }
+        }
+
+        covariance
+    }
+
+    /// Builds a covariance matrix from Gaia style catalog columns for the object in astrometry.
+    /// errors are ra_error, dec_error (both mas, ra_error on the sky), parallax_error (mas), pmra_error and pmdec_error (both mas/yr, pmra_error on the sky)
+    /// and correlations are the ten Gaia correlation coefficients in catalog order (ra_dec_corr, ra_parallax_corr, ra_pmra_corr, ra_pmdec_corr,
+    /// dec_parallax_corr, dec_pmra_corr, dec_pmdec_corr, parallax_pmra_corr, parallax_pmdec_corr and pmra_pmdec_corr).
+    /// radial_velocity_error is in km/s and is uncorrelated with the rest, use 0 for the 5 parameter case.
+    pub fn covariance_from_gaia(
+        astrometry: AstrometricParameters,
+        errors: [f64; 5],
+        correlations: [f64; 10],
+        radial_velocity_error: f64,
+    ) -> Matrix6 {
+        let cos_dec = astrometry.declination.to_radians().cos();
+
+        //Gaia order (ra, dec, parallax, pmra, pmdec) in the units and order used by spv-rs
+        let index = [1, 2, 0, 3, 4];
+        let scaled = [
+            errors[0] / cos_dec / 3600000.,
+            errors[1] / 3600000.,
+            errors[2],
+            errors[3] / cos_dec / 1000.,
+            errors[4] / 1000.,
+        ];
+
+        let mut spv_errors = [0.; 6];
+        for (i, error) in scaled.iter().enumerate() {
+            spv_errors[index[i]] = *error;
+        }
+        spv_errors[5] = radial_velocity_error;
+
+        let mut spv_correlations = [[0.; 6]; 6];
+        let mut k = 0;
+        for i in 0..5 {
+            for j in (i + 1)..5 {
+                spv_correlations[index[i]][index[j]] = correlations[k];
+                spv_correlations[index[j]][index[i]] = correlations[k];
+                k += 1;
+            }
+        }
+
+        covariance_from_errors(spv_errors, &spv_correlations)
+    }
+
+    /// Analytic Jacobian of the cartesian position (meters) and velocity (meters/second) with respect to the astrometric parameters.
+    /// Row i is the derivative of x, y, z, v_x, v_y and v_z in that order and collum j is the parameter in [`AstrometricParameters`] order.
+    pub fn cartesian_jacobian(astrometry: AstrometricParameters) -> Matrix6 {
+        let distance_si = PARSEC / (astrometry.parallax / 1000.);
+        let deg_to_rad = 1_f64.to_radians();
+        //as/yr to rad/s
+        let pm_to_si = (1. / 3600_f64).to_radians() / JULIAN_YEAR;
+        let radial_velocity_si = astrometry.radial_velocity * 1000.;
+
+        let triad = normal_triad(astrometry.right_ascension, astrometry.declination);
+        let p = triad.x_axis;
+        let q = triad.y_axis;
+        let u = triad.z_axis;
+
+        let (sin_dec, cos_dec) = astrometry.declination.to_radians().sin_cos();
+        let (sin_ra, cos_ra) = astrometry.right_ascension.to_radians().sin_cos();
+
+        let pm_ra = astrometry.proper_motion_ra * pm_to_si;
+        let pm_dec = astrometry.proper_motion_dec * pm_to_si;
+        let tangential = (p * (pm_ra * cos_dec)) + (q * pm_dec);
+
+        //Derivatives of the triad
+        let dp_dra = DVec3::new(-cos_ra, -sin_ra, 0.);
+        let dq_dra = -p * sin_dec;
+        let du_dra = p * cos_dec;
+        let dq_ddec = -u;
+        let du_ddec = q;
+
+        let dr = [
+            -u * (distance_si / astrometry.parallax),
+            du_dra * distance_si * deg_to_rad,
+            du_ddec * distance_si * deg_to_rad,
+            DVec3::ZERO,
+            DVec3::ZERO,
+            DVec3::ZERO,
+        ];
+
+        let dv = [
+            -tangential * (distance_si / astrometry.parallax),
+            (((dp_dra * (pm_ra * cos_dec)) + (dq_dra * pm_dec)) * distance_si
+                + (du_dra * radial_velocity_si))
+                * deg_to_rad,
+            (((p * (-pm_ra * sin_dec)) + (dq_ddec * pm_dec)) * distance_si
+                + (du_ddec * radial_velocity_si))
+                * deg_to_rad,
+            p * (distance_si * cos_dec * pm_to_si),
+            q * (distance_si * pm_to_si),
+            u * 1000.,
+        ];
+
+        let mut jacobian = [[0.; 6]; 6];
+        for j in 0..6 {
+            let r = dr[j].to_array();
+            let v = dv[j].to_array();
+            for i in 0..3 {
+                jacobian[i][j] = r[i];
+                jacobian[i + 3][j] = v[i];
+            }
+        }
+
+        jacobian
+    }
+
+    /// Propagates an astrometric covariance through [`cartesian_jacobian`] to the covariance of the cartesian position and velocity.
+    pub fn cartesian_covariance(
+        astrometry: AstrometricParameters,
+        covariance: &Matrix6,
+    ) -> Matrix6 {
+        let jacobian = cartesian_jacobian(astrometry);
+
+        multiply(&multiply(&jacobian, covariance), &transpose(&jacobian))
+    }
+
+    /// Position and velocity of a single celestial object relative to the sun together with their covariance.
+    /// covariance is the 6x6 astrometric covariance, for a 5 parameter solution leave the radial velocity row and collum as zero.
+    pub fn state_with_covariance(
+        astrometry: AstrometricParameters,
+        covariance: &Matrix6,
+    ) -> StateWithCovariance {
+        StateWithCovariance {
+            position: position(
+                astrometry.parallax,
+                astrometry.right_ascension,
+                astrometry.declination,
+            ),
+            velocity: velocity(
+                astrometry.parallax,
+                astrometry.right_ascension,
+                astrometry.declination,
+                astrometry.proper_motion_ra,
+                astrometry.proper_motion_dec,
+                astrometry.radial_velocity,
+            ),
+            covariance: cartesian_covariance(astrometry, covariance),
+        }
+    }
+
+    /// Square root of the diagonal of a covariance matrix.
+    pub fn standard_errors(covariance: &Matrix6) -> [f64; 6] {
+        let mut errors = [0.; 6];
+        for (i, error) in errors.iter_mut().enumerate() {
+            *error = covariance[i][i].sqrt();
+        }
+
+        errors
+    }
+
+    /// Matrix product of two 6x6 matrices.
+    pub fn multiply(a: &Matrix6, b: &Matrix6) -> Matrix6 {
+        let mut product = [[0.; 6]; 6];
+        for (i, row) in product.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..6).map(|k| a[i][k] * b[k][j]).sum();
+            }
+        }
+
+        product
+    }
+
+    /// Transpose of a 6x6 matrix.
+    pub fn transpose(a: &Matrix6) -> Matrix6 {
+        let mut transposed = [[0.; 6]; 6];
+        for (i, row) in transposed.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = a[j][i];
+            }
+        }
+
+        transposed
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        //Cartesian state of the parameters in AstrometricParameters order
+        fn state(parameters: [f64; 6]) -> [f64; 6] {
+            let r = position(parameters[0], parameters[1], parameters[2]);
+            let v = velocity(
+                parameters[0],
+                parameters[1],
+                parameters[2],
+                parameters[3],
+                parameters[4],
+                parameters[5],
+            );
+
+            [r.x, r.y, r.z, v.x, v.y, v.z]
+        }
+
+        #[test]
+        fn jacobian_matches_finite_differences() {
+            let parameters = [12.5, 210.3, -35.2, 0.04, -0.11, 23.];
+            let jacobian = cartesian_jacobian(AstrometricParameters::new(
+                parameters[0],
+                parameters[1],
+                parameters[2],
+                parameters[3],
+                parameters[4],
+                parameters[5],
+            ));
+            let steps = [1e-4, 1e-7, 1e-7, 1e-6, 1e-6, 1e-4];
+
+            for (j, step) in steps.iter().enumerate() {
+                let mut plus = parameters;
+                let mut minus = parameters;
+                plus[j] += step;
+                minus[j] -= step;
+
+                let (state_plus, state_minus) = (state(plus), state(minus));
+                for i in 0..6 {
+                    let numeric = (state_plus[i] - state_minus[i]) / (2. * step);
+                    let scale = jacobian[i][j].abs().max(1e-3);
+                    assert!((numeric - jacobian[i][j]).abs() / scale < 1e-5);
+                }
+            }
+        }
+
+        #[test]
+        fn parallax_error_maps_to_distance_error() {
+            let astrometry = AstrometricParameters::new(10., 45., 30., 0., 0., 0.);
+            let mut covariance = [[0.; 6]; 6];
+            covariance[0][0] = 0.1_f64.powf(2.);
+
+            let propagated = state_with_covariance(astrometry, &covariance);
+            let errors = propagated.standard_errors();
+            let distance_error =
+                (errors[0].powf(2.) + errors[1].powf(2.) + errors[2].powf(2.)).sqrt();
+
+            //sigma_d = d * sigma_parallax / parallax
+            let expected = propagated.position.length() * 0.1 / 10.;
+            assert!((distance_error / expected - 1.).abs() < 1e-12);
+            assert!(errors[3..].iter().all(|error| *error == 0.));
+        }
+
+        #[test]
+        fn gaia_covariance_is_reordered_and_scaled() {
+            let astrometry = AstrometricParameters::new(10., 45., 60., 0., 0., 0.);
+            let covariance = covariance_from_gaia(
+                astrometry,
+                [0.36, 0.72, 0.5, 2., 1.],
+                [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9, -0.5],
+                1.5,
+            );
+
+            //Parallax first, ra_error on the sky divided by cos(declination) and converted to degrees
+            assert!((covariance[0][0] - 0.25).abs() < 1e-15);
+            assert!((covariance[1][1].sqrt() - (0.72 / 3600000.)).abs() < 1e-18);
+            assert!((covariance[2][2].sqrt() - (0.72 / 3600000.)).abs() < 1e-18);
+            assert!((covariance[3][3].sqrt() - 0.004).abs() < 1e-15);
+            assert!((covariance[5][5] - 2.25).abs() < 1e-15);
+            //ra_parallax_corr
+            let correlation = covariance[0][1] / (covariance[0][0] * covariance[1][1]).sqrt();
+            assert!((correlation - 0.2).abs() < 1e-12);
+            //pmra_pmdec_corr
+            let correlation = covariance[3][4] / (covariance[3][3] * covariance[4][4]).sqrt();
+            assert!((correlation + 0.5).abs() < 1e-12);
+            assert_eq!(covariance, transpose(&covariance));
+        }
+    }
+}
+
+/// Monte Carlo propagation of astrometric uncertainties for when linear propagation through 1/parallax breaks down (small parallax over error).
+/// Samples are drawn from a multivariate normal distribution with a seeded ChaCha generator so the results are reproducible from the seed.
+pub mod monte_carlo {
+    use super::astrometry::AstrometricParameters;
+    use super::position::position;
+    use super::uncertainty::Matrix6;
+    use super::velocity::velocity;
+    use glam::f64::DVec3;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+    use rand_distr::StandardNormal;
+
+    /// Lower triangular Cholesky factor of a symmetric positive semi-definite matrix.
+    /// Rows and collums with zero variance (e.g. a missing radial velocity) are left as zero instead of failing.
+    pub fn cholesky<const N: usize>(matrix: &[[f64; N]; N]) -> [[f64; N]; N] {
+        let mut lower = [[0_f64; N]; N];
+        for j in 0..N {
+            let diagonal = matrix[j][j] - (0..j).map(|k| lower[j][k].powf(2.)).sum::<f64>();
+            if diagonal <= f64::EPSILON * matrix[j][j].abs() {
+                continue;
+            }
+            lower[j][j] = diagonal.sqrt();
+
+            for i in (j + 1)..N {
+                lower[i][j] = (matrix[i][j]
+                    - (0..j).map(|k| lower[i][k] * lower[j][k]).sum::<f64>())
+                    / lower[j][j];
+            }
+        }
+
+        lower
+    }
+
+    /// Draws samples from a multivariate normal distribution with mean and covariance, reproducible from seed.
+    /// Works for any number of parameters, e.g. the six astrometric parameters or the seven orbital elements of a companion.
+    pub fn sample_multivariate_normal<const N: usize>(
+        mean: [f64; N],
+        covariance: &[[f64; N]; N],
+        samples: usize,
+        seed: u64,
+    ) -> std::vec::Vec<[f64; N]> {
+        let lower = cholesky(covariance);
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+
+        let mut vec = vec![];
+        for _n in 0..samples {
+            let mut normal = [0.; N];
+            for value in normal.iter_mut() {
+                *value = rng.sample(StandardNormal);
+            }
+
+            let mut sample = mean;
+            for (i, value) in sample.iter_mut().enumerate() {
+                *value += (0..=i).map(|k| lower[i][k] * normal[k]).sum::<f64>();
+            }
+
+            vec.push(sample);
+        }
+
+        vec
+    }
+
+    /// Draws samples of the astrometric parameters with covariance ordered as in [`super::uncertainty`], reproducible from seed.
+    /// Samples with a zero or negative parallax are kept so the caller can decide how to treat them, [`monte_carlo_states`] drops them.
+    pub fn sample_astrometry(
+        astrometry: AstrometricParameters,
+        covariance: &Matrix6,
+        samples: usize,
+        seed: u64,
+    ) -> std::vec::Vec<AstrometricParameters> {
+        let mean = [
+            astrometry.parallax,
+            astrometry.right_ascension,
+            astrometry.declination,
+            astrometry.proper_motion_ra,
+            astrometry.proper_motion_dec,
+            astrometry.radial_velocity,
+        ];
+
+        sample_multivariate_normal(mean, covariance, samples, seed)
+            .iter()
+            .map(|s| AstrometricParameters::new(s[0], s[1], s[2], s[3], s[4], s[5]))
+            .collect()
+    }
+
+    /// Pushes every sample of the parameters through function and returns the full sample set, reproducible from seed.
+    /// Use this with the companion functions, for example with mean and covariance over (a, e, period, t_p, lotn, aop, i)
+    /// and a function calling [`super::position::companion_relative_position`].
+    pub fn monte_carlo<const N: usize, T, F: Fn(&[f64; N]) -> T>(
+        mean: [f64; N],
+        covariance: &[[f64; N]; N],
+        samples: usize,
+        seed: u64,
+        function: F,
+    ) -> std::vec::Vec<T> {
+        sample_multivariate_normal(mean, covariance, samples, seed)
+            .iter()
+            .map(function)
+            .collect()
+    }
+
+    /// Cartesian position (meters) and velocity (meters/second) of every sample of the astrometric parameters, reproducible from seed.
+    /// Samples with a zero or negative parallax have no distance, so they are dropped instead of turning into infinite or mirrored states.
+    /// This matters when the parallax error is a sizeable fraction of the parallax, where the dropped samples also bias the remaining ones
+    /// towards nearby distances. Use [`sample_astrometry`] to handle those samples differently.
+    /// Output is a vector of position and velocity pairs as given by [`super::position::position`] and [`super::velocity::velocity`]
+    /// and the number of dropped samples in that order.
+    pub fn monte_carlo_states(
+        astrometry: AstrometricParameters,
+        covariance: &Matrix6,
+        samples: usize,
+        seed: u64,
+    ) -> (std::vec::Vec<(DVec3, DVec3)>, usize) {
+        let sampled = sample_astrometry(astrometry, covariance, samples, seed);
+
+        let states: std::vec::Vec<(DVec3, DVec3)> = sampled
+            .iter()
+            .filter(|a| a.parallax > 0.)
+            .map(|a| {
+                (
+                    position(a.parallax, a.right_ascension, a.declination),
+                    velocity(
+                        a.parallax,
+                        a.right_ascension,
+                        a.declination,
+                        a.proper_motion_ra,
+                        a.proper_motion_dec,
+                        a.radial_velocity,
+                    ),
+                )
+            })
+            .collect();
+        let dropped = sampled.len() - states.len();
+
+        (states, dropped)
+    }
+
+    /// Percentiles (0 to 100) of values using linear interpolation between the closest ranks, NaN values are ignored.
+    pub fn percentiles(values: &[f64], percentiles: &[f64]) -> std::vec::Vec<f64> {
+        let mut sorted: std::vec::Vec<f64> =
+            values.iter().copied().filter(|v| !v.is_nan()).collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        percentiles
+            .iter()
+            .map(|p| {
+                if sorted.is_empty() {
+                    return f64::NAN;
+                }
+                let rank = (p / 100.).clamp(0., 1.) * (sorted.len() - 1) as f64;
+                let lower = rank.floor() as usize;
+                let upper = rank.ceil() as usize;
+
+                sorted[lower] + ((sorted[upper] - sorted[lower]) * (rank - lower as f64))
+            })
+            .collect()
+    }
+
+    /// Percentiles (0 to 100) of every component of a set of states from [`monte_carlo_states`].
+    /// Output has one entry per percentile with x, y, z, v_x, v_y and v_z in that order in meters and meters/second.
+    pub fn state_percentiles(
+        states: &[(DVec3, DVec3)],
+        percentiles: &[f64],
+    ) -> std::vec::Vec<[f64; 6]> {
+        let mut output = vec![[0.; 6]; percentiles.len()];
+        for component in 0..6 {
+            let values: std::vec::Vec<f64> = states
+                .iter()
+                .map(|(p, v)| {
+                    if component < 3 {
+                        p[component]
+                    } else {
+                        v[component - 3]
+                    }
+                })
+                .collect();
+
+            for (k, value) in self::percentiles(&values, percentiles).iter().enumerate() {
+                output[k][component] = *value;
+            }
+        }
+
+        output
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn cholesky_reproduces_the_matrix() {
+            let matrix = [[4., 2., 0.6], [2., 2., 0.5], [0.6, 0.5, 3.]];
+            let lower = cholesky(&matrix);
+
+            for i in 0..3 {
+                for j in 0..3 {
+                    let product: f64 = (0..3).map(|k| lower[i][k] * lower[j][k]).sum();
+                    assert!((product - matrix[i][j]).abs() < 1e-14);
+                }
+            }
+
+            //Zero variance rows are left as zero
+            let lower = cholesky(&[[1., 0.], [0., 0.]]);
+            assert_eq!(lower, [[1., 0.], [0., 0.]]);
+        }
+
+        #[test]
+        fn samples_have_the_requested_moments() {
+            let mean = [1., -2.];
+            let covariance = [[4., 1.2], [1.2, 1.]];
+            let samples = sample_multivariate_normal(mean, &covariance, 200000, 7);
+
+            let n = samples.len() as f64;
+            let mean_x = samples.iter().map(|s| s[0]).sum::<f64>() / n;
+            let mean_y = samples.iter().map(|s| s[1]).sum::<f64>() / n;
+            let covariance_xy = samples
+                .iter()
+                .map(|s| (s[0] - mean_x) * (s[1] - mean_y))
+                .sum::<f64>()
+                / n;
+
+            assert!((mean_x - 1.).abs() < 0.02);
+            assert!((mean_y + 2.).abs() < 0.01);
+            assert!((covariance_xy - 1.2).abs() < 0.03);
+
+            //Same seed gives the same samples
+            assert_eq!(
+                samples[..10],
+                sample_multivariate_normal(mean, &covariance, 10, 7)[..]
+            );
+        }
+
+        #[test]
+        fn non_positive_parallax_samples_are_dropped() {
+            //Parallax over error of 1 so about 16% of the samples have a negative parallax
+            let astrometry = AstrometricParameters::new(1., 80., 10., 0.001, -0.002, 5.);
+            let mut covariance = [[0.; 6]; 6];
+            covariance[0][0] = 1.;
+
+            let samples = sample_astrometry(astrometry, &covariance, 5000, 3);
+            let non_positive = samples.iter().filter(|a| a.parallax <= 0.).count();
+            assert!(non_positive > 500);
+
+            let (states, dropped) = monte_carlo_states(astrometry, &covariance, 5000, 3);
+            assert_eq!(dropped, non_positive);
+            assert_eq!(states.len() + dropped, 5000);
+            assert!(states.iter().all(|(p, v)| p.is_finite()
+                && v.is_finite()
+                && p.dot(position(1., 80., 10.)) > 0.));
+        }
+
+        #[test]
+        fn percentiles_interpolate_between_ranks() {
+            let values = [3., 1., f64::NAN, 2., 4.];
+
+            assert_eq!(percentiles(&values, &[0., 50., 100.]), vec![1., 2.5, 4.]);
+            assert!(percentiles(&[], &[50.])[0].is_nan());
+        }
+    }
+}
+
+/// Embedded analytic ephemeris of the Earth so that apparent places and barycentric corrections work offline.
+/// The heliocentric Earth comes from the truncated VSOP87D series of Meeus (Astronomical Algorithms, 2nd ed., Appendix III),
+/// and the offset of the sun from the solar system barycenter from the Keplerian elements of the major planets by Standish (JPL, valid 1800 to 2050).
+/// Positions are in meters and velocities in meters/second in the ICRS, julian_date is in TT.
+pub mod ephemeris {
+    use super::common::{solve_kepler, KEPLER_MAX_ITERATIONS, KEPLER_TOLERANCE};
+    use super::coordinate_transforms::euler_angle_transformations;
+    use super::coordinate_transforms::{ecliptic_j2000_to_icrs, ecliptic_of_date_to_equatorial};
+    use super::precession::mean_of_date_to_icrs;
+    use super::time::{julian_date_to_epoch, J2000_JD};
+    use glam::f64::DVec3;
+    use std::f64::consts::PI;
+
+    /// Astronomical unit in meters.
+    pub const AU: f64 = 149597870700.;
+
+    //VSOP87D series for the Earth as (amplitude in 1e-8 rad or au, phase in rad, frequency in rad per Julian millennium)
+    #[rustfmt::skip]
+    const L0: [(f64, f64, f64); 64] = [
+        (175347046., 0., 0.), (3341656., 4.6692568, 6283.07585), (34894., 4.6261, 12566.1517),
+        (3497., 2.7441, 5753.3849), (3418., 2.8289, 3.5231), (3136., 3.6277, 77713.7715),
+        (2676., 4.4181, 7860.4194), (2343., 6.1352, 3930.2097), (1324., 0.7425, 11506.7698),
+        (1273., 2.0371, 529.691), (1199., 1.1096, 1577.3435), (990., 5.233, 5884.927),
+        (902., 2.045, 26.298), (857., 3.508, 398.149), (780., 1.179, 5223.694),
+        (753., 2.533, 5507.553), (505., 4.583, 18849.228), (492., 4.205, 775.523),
+        (357., 2.92, 0.067), (317., 5.849, 11790.629), (284., 1.899, 796.298),
+        (271., 0.315, 10977.079), (243., 0.345, 5486.778), (206., 4.806, 2544.314),
+        (205., 1.869, 5573.143), (202., 2.458, 6069.777), (156., 0.833, 213.299),
+        (132., 3.411, 2942.463), (126., 1.083, 20.775), (115., 0.645, 0.98),
+        (103., 0.636, 4694.003), (102., 0.976, 15720.839), (102., 4.267, 7.114),
+        (99., 6.21, 2146.17), (98., 0.68, 155.42), (86., 5.98, 161000.69),
+        (85., 1.3, 6275.96), (85., 3.67, 71430.7), (80., 1.81, 17260.15),
+        (79., 3.04, 12036.46), (75., 1.76, 5088.63), (74., 3.5, 3154.69),
+        (74., 4.68, 801.82), (70., 0.83, 9437.76), (62., 3.98, 8827.39),
+        (61., 1.82, 7084.9), (57., 2.78, 6286.6), (56., 4.39, 14143.5),
+        (56., 3.47, 6279.55), (52., 0.19, 12139.55), (52., 1.33, 1748.02),
+        (51., 0.28, 5856.48), (49., 0.49, 1194.45), (41., 5.37, 8429.24),
+        (41., 2.4, 19651.05), (39., 6.17, 10447.39), (37., 6.04, 10213.29),
+        (37., 2.57, 1059.38), (36., 1.71, 2352.87), (36., 1.78, 6812.77),
+        (33., 0.59, 17789.85), (30., 0.44, 83996.85), (30., 2.74, 1349.87),
+        (25., 3.16, 4690.48),
+    ];
+
+    #[rustfmt::skip]
+    const L1: [(f64, f64, f64); 34] = [
+        (628331966747., 0., 0.), (206059., 2.678235, 6283.07585), (4303., 2.6351, 12566.1517),
+        (425., 1.59, 3.523), (119., 5.796, 26.298), (109., 2.966, 1577.344),
+        (93., 2.59, 18849.23), (72., 1.14, 529.69), (68., 1.87, 398.15),
+        (67., 4.41, 5507.55), (59., 2.89, 5223.69), (56., 2.17, 155.42),
+        (45., 0.4, 796.3), (36., 0.47, 775.52), (29., 2.65, 7.11),
+        (21., 5.34, 0.98), (19., 1.85, 5486.78), (19., 4.97, 213.3),
+        (17., 2.99, 6275.96), (16., 0.03, 2544.31), (16., 1.43, 2146.17),
+        (15., 1.21, 10977.08), (12., 2.83, 1748.02), (12., 3.26, 5088.63),
+        (12., 5.27, 1194.45), (12., 2.08, 4694.), (11., 0.77, 553.57),
+        (10., 1.3, 6286.6), (10., 4.24, 1349.87), (9., 2.7, 242.73),
+        (9., 5.64, 951.72), (8., 5.3, 2352.87), (6., 2.65, 9437.76),
+        (6., 4.67, 4690.48),
+    ];
+
+    #[rustfmt::skip]
+    const L2: [(f64, f64, f64); 20] = [
+        (52919., 0., 0.), (8720., 1.0721, 6283.0758), (309., 0.867, 12566.152),
+        (27., 0.05, 3.52), (16., 5.19, 26.3), (16., 3.68, 155.42),
+        (10., 0.76, 18849.23), (9., 2.06, 77713.77), (7., 0.83, 775.52),
+        (5., 4.66, 1577.34), (4., 1.03, 7.11), (4., 3.44, 5573.14),
+        (3., 5.14, 796.3), (3., 6.05, 5507.55), (3., 1.19, 242.73),
+        (3., 6.12, 529.69), (3., 0.31, 398.15), (3., 2.28, 553.57),
+        (2., 4.38, 5223.69), (2., 3.75, 0.98),
+    ];
+
+    #[rustfmt::skip]
+    const L3: [(f64, f64, f64); 7] = [
+        (289., 5.844, 6283.076), (35., 0., 0.), (17., 5.49, 12566.15),
+        (3., 5.2, 155.42), (1., 4.72, 3.52), (1., 5.3, 18849.23),
+        (1., 5.97, 242.73),
+    ];
+
+    #[rustfmt::skip]
+    const L4: [(f64, f64, f64); 3] = [(114., PI, 0.), (8., 4.13, 6283.08), (1., 3.84, 12566.15)];
+
+    const L5: [(f64, f64, f64); 1] = [(1., PI, 0.)];
+
+    #[rustfmt::skip]
+    const B0: [(f64, f64, f64); 5] = [
+        (280., 3.199, 84334.662), (102., 5.422, 5507.553), (80., 3.88, 5223.69),
+        (44., 3.7, 2352.87), (32., 4., 1577.34),
+    ];
+
+    const B1: [(f64, f64, f64); 2] = [(9., 3.9, 5507.55), (6., 1.73, 5223.69)];
+
+    #[rustfmt::skip]
+    const R0: [(f64, f64, f64); 40] = [
+        (100013989., 0., 0.), (1670700., 3.0984635, 6283.07585), (13956., 3.05525, 12566.1517),
+        (3084., 5.1985, 77713.7715), (1628., 1.1739, 5753.3849), (1576., 2.8469, 7860.4194),
+        (925., 5.453, 11506.77), (542., 4.564, 3930.21), (472., 3.661, 5884.927),
+        (346., 0.964, 5507.553), (329., 5.9, 5223.694), (307., 0.299, 5573.143),
+        (243., 4.273, 11790.629), (212., 5.847, 1577.344), (186., 5.022, 10977.079),
+        (175., 3.012, 18849.228), (110., 5.055, 5486.778), (98., 0.89, 6069.78),
+        (86., 5.69, 15720.84), (86., 1.27, 161000.69), (65., 0.27, 17260.15),
+        (63., 0.92, 529.69), (57., 2.01, 83996.85), (56., 5.24, 71430.7),
+        (49., 3.25, 2544.31), (47., 2.58, 775.52), (45., 5.54, 9437.76),
+        (43., 6.01, 6275.96), (39., 5.36, 4694.), (38., 2.39, 8827.39),
+        (37., 0.83, 19651.05), (37., 4.9, 12139.55), (36., 1.67, 12036.46),
+        (35., 1.84, 2942.46), (33., 0.24, 7084.9), (32., 0.18, 5088.63),
+        (32., 1.78, 398.15), (28., 1.21, 6286.6), (28., 1.9, 6279.55),
+        (26., 4.59, 10447.39),
+    ];
+
+    #[rustfmt::skip]
+    const R1: [(f64, f64, f64); 10] = [
+        (103019., 1.10749, 6283.07585), (1721., 1.0644, 12566.1517), (702., PI, 0.),
+        (32., 1.02, 18849.23), (31., 2.84, 5507.55), (25., 1.32, 5223.69),
+        (18., 1.42, 1577.34), (10., 5.91, 10977.08), (9., 1.42, 6275.96),
+        (9., 0.27, 5486.78),
+    ];
+
+    #[rustfmt::skip]
+    const R2: [(f64, f64, f64); 6] = [
+        (4359., 5.7846, 6283.0758), (124., 5.579, 12566.152), (12., PI, 0.),
+        (9., 3.63, 77713.77), (6., 1.87, 5573.14), (3., 5.47, 18849.23),
+    ];
+
+    const R3: [(f64, f64, f64); 2] = [(145., 4.273, 6283.076), (7., 3.92, 12566.15)];
+
+    const R4: [(f64, f64, f64); 1] = [(4., 2.56, 6283.08)];
+
+    //Keplerian elements of the planets at J2000 and their rates per Julian century (Standish, valid 1800 to 2050):
+    //a (au), e, I, L, longitude of perihelion and longitude of the node (degrees), followed by the sun/planet mass ratio
+    #[rustfmt::skip]
+    const PLANETS: [([f64; 6], [f64; 6], f64); 8] = [
+        ([0.38709927, 0.20563593, 7.00497902, 252.2503235, 77.45779628, 48.33076593],
+         [0.00000037, 0.00001906, -0.00594749, 149472.67411175, 0.16047689, -0.12534081], 6023600.),
+        ([0.72333566, 0.00677672, 3.39467605, 181.9790995, 131.60246718, 76.67984255],
+         [0.0000039, -0.00004107, -0.0007889, 58517.81538729, 0.00268329, -0.27769418], 408523.71),
+        ([1.00000261, 0.01671123, -0.00001531, 100.46457166, 102.93768193, 0.],
+         [0.00000562, -0.00004392, -0.01294668, 35999.37244981, 0.32327364, 0.], 328900.56),
+        ([1.52371034, 0.0933941, 1.84969142, -4.55343205, -23.94362959, 49.55953891],
+         [0.00001847, 0.00007882, -0.00813131, 19140.30268499, 0.44441088, -0.29257343], 3098708.),
+        ([5.202887, 0.04838624, 1.30439695, 34.39644051, 14.72847983, 100.47390909],
+         [-0.00011607, -0.00013253, -0.00183714, 3034.74612775, 0.21252668, 0.20469106], 1047.3486),
+        ([9.53667594, 0.05386179, 2.48599187, 49.95424423, 92.59887831, 113.66242448],
+         [-0.0012506, -0.00050991, 0.00193609, 1222.49362201, -0.41897216, -0.28867794], 3497.898),
+        ([19.18916464, 0.04725744, 0.77263783, 313.23810451, 170.9542763, 74.01692503],
+         [-0.00196176, -0.00004397, -0.00242939, 428.48202785, 0.40805281, 0.04240589], 22902.98),
+        ([30.06992276, 0.00859048, 1.77004347, -55.12002969, 44.96476227, 131.78422574],
+         [0.00026291, 0.00005105, 0.00035372, 218.45945325, -0.32241464, -0.00508664], 19412.24),
+    ];
+
+    //Step in days for the numerical derivatives of the positions
+    const STEP: f64 = 0.01;
+
+    //Sum of one VSOP87 series at t Julian millennia
+    fn series(terms: &[(f64, f64, f64)], t: f64) -> f64 {
+        terms
+            .iter()
+            .map(|(a, b, c)| a * (b + (c * t)).cos())
+            .sum::<f64>()
+    }
+
+    //Sum of series multiplied by increasing powers of t, scaled from 1e-8
+    fn power_series(series_list: &[&[(f64, f64, f64)]], t: f64) -> f64 {
+        series_list
+            .iter()
+            .rev()
+            .fold(0., |sum, terms| (sum * t) + series(terms, t))
+            / 100000000.
+    }
+
+    /// Heliocentric position of the Earth in meters in the ICRS at julian_date (TT).
+    pub fn earth_heliocentric_position(julian_date: f64) -> DVec3 {
+        let t = (julian_date - J2000_JD) / 365250.;
+
+        let l = power_series(&[&L0, &L1, &L2, &L3, &L4, &L5], t);
+        let b = power_series(&[&B0, &B1], t);
+        let r = power_series(&[&R0, &R1, &R2, &R3, &R4], t);
+
+        //Ecliptic and equinox of date to the ICRS
+        let ecliptic = DVec3::new(r * b.cos() * l.cos(), r * b.cos() * l.sin(), r * b.sin());
+        let epoch = julian_date_to_epoch(julian_date);
+
+        mean_of_date_to_icrs(ecliptic_of_date_to_equatorial(ecliptic, epoch), epoch) * AU
+    }
+
+    /// Heliocentric position of a planet in meters in the ICRS at julian_date (TT) from its Keplerian elements,
+    /// planet is 0 for Mercury, 1 for Venus, 2 for the Earth-Moon barycenter and so on up to 7 for Neptune.
+    pub fn planet_heliocentric_position(planet: usize, julian_date: f64) -> DVec3 {
+        let t = (julian_date - J2000_JD) / 36525.;
+        let (elements, rates, _) = PLANETS[planet];
+
+        let a = elements[0] + (rates[0] * t);
+        let e = elements[1] + (rates[1] * t);
+        let i = elements[2] + (rates[2] * t);
+        let l = elements[3] + (rates[3] * t);
+        let longitude_of_perihelion = elements[4] + (rates[4] * t);
+        let lotn = elements[5] + (rates[5] * t);
+
+        let aop = longitude_of_perihelion - lotn;
+        let mean_anomaly = (l - longitude_of_perihelion).to_radians();
+
+        let eccentric_anomaly =
+            solve_kepler(mean_anomaly, e, KEPLER_TOLERANCE, KEPLER_MAX_ITERATIONS)
+                .eccentric_anomaly;
+
+        let x = a * (eccentric_anomaly.cos() - e);
+        let y = a * (1. - e.powf(2.)).sqrt() * eccentric_anomaly.sin();
+
+        let euler_angle_transformations = euler_angle_transformations(lotn, aop, i);
+        let ecliptic =
+            (euler_angle_transformations.x_axis * x) + (euler_angle_transformations.y_axis * y);
+
+        ecliptic_j2000_to_icrs(ecliptic) * AU
+    }
+
+    /// Position of the sun relative to the solar system barycenter in meters in the ICRS at julian_date (TT).
+    pub fn sun_barycentric_position(julian_date: f64) -> DVec3 {
+        let mut total_mass = 1.;
+        let mut weighted = DVec3::ZERO;
+        for (planet, (_, _, mass_ratio)) in PLANETS.iter().enumerate() {
+            total_mass += 1. / mass_ratio;
+            weighted += planet_heliocentric_position(planet, julian_date) / *mass_ratio;
+        }
+
+        -weighted / total_mass
+    }
+
+    /// Position of the Earth relative to the solar system barycenter in meters in the ICRS at julian_date (TT).
+    pub fn earth_barycentric_position(julian_date: f64) -> DVec3 {
+        earth_heliocentric_position(julian_date) + sun_barycentric_position(julian_date)
+    }
+
+    /// Velocity of the Earth relative to the sun in meters/second in the ICRS at julian_date (TT).
+    pub fn earth_heliocentric_velocity(julian_date: f64) -> DVec3 {
+        (earth_heliocentric_position(julian_date + STEP)
+            - earth_heliocentric_position(julian_date - STEP))
+            / (2. * STEP * 86400.)
+    }
+
+    /// Velocity of the Earth relative to the solar system barycenter in meters/second in the ICRS at julian_date (TT).
+    pub fn earth_barycentric_velocity(julian_date: f64) -> DVec3 {
+        (earth_barycentric_position(julian_date + STEP)
+            - earth_barycentric_position(julian_date - STEP))
+            / (2. * STEP * 86400.)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        //SOFA iauEpv00 test date
+        const JULIAN_DATE: f64 = 2400000.5 + 53411.52501161;
+        const AU_PER_DAY: f64 = AU / 86400.;
+
+        #[test]
+        fn earth_barycentric_velocity_matches_sofa() {
+            let expected = DVec3::new(
+                -0.010918742681168233,
+                -0.012465254617328616,
+                -0.0054047731809662315,
+            ) * AU_PER_DAY;
+
+            assert!((earth_barycentric_velocity(JULIAN_DATE) - expected).length() < 0.3);
+        }
+
+        #[test]
+        fn earth_barycentric_position_matches_sofa() {
+            //The truncated VSOP87 series and the planetary elements are good to a few thousand km
+            let expected =
+                DVec3::new(-0.7714104440491112, 0.5598412061824172, 0.24259962777224525) * AU;
+
+            assert!((earth_barycentric_position(JULIAN_DATE) - expected).length() < 3e6);
+        }
+
+        #[test]
+        fn earth_heliocentric_distance_is_about_one_au() {
+            for day in (0..365).step_by(30) {
+                let julian_date = J2000_JD + day as f64;
+                let distance = earth_heliocentric_position(julian_date).length() / AU;
+                assert!((0.983..1.017).contains(&distance));
+
+                let speed = earth_heliocentric_velocity(julian_date).length();
+                assert!((29200.0..30400.).contains(&speed));
+            }
+        }
+
+        #[test]
+        fn sun_stays_within_the_barycentric_wobble() {
+            //Mostly Jupiter, the sun is never more than about 2.2 solar radii from the barycenter
+            let offset = sun_barycentric_position(JULIAN_DATE).length();
+            assert!(offset > 1e8 && offset < 1.6e9);
+        }
+    }
+}
+
+/// Apparent places of stars as seen from the geocenter, following the classical reduction in the IERS Conventions and SOFA:
+/// space motion to the date of observation, annual parallax, optional light deflection by the sun, annual aberration and finally precession and nutation.
+/// The Earth comes from [`super::ephemeris`], which limits the accuracy to the milliarcsecond level.
+pub mod apparent {
+    use super::astrometry::{propagate_epoch, AstrometricParameters, SPEED_OF_LIGHT};
+    use super::coordinate_transforms::{spherical_angles, unit_vector};
+    use super::ephemeris::{
+        earth_barycentric_position, earth_barycentric_velocity, earth_heliocentric_position, AU,
+    };
+    use super::precession::icrs_to_true_of_date;
+    use super::time::julian_date_to_epoch;
+    use glam::f64::{DVec2, DVec3};
+
+    /// Schwarzschild radius of the sun in au.
+    pub const SCHWARZSCHILD_RADIUS_SUN: f64 = 1.97412574336e-8;
+
+    /// Shifts the barycentric unit vector direction of a star with parallax in mas to the position of the observer,
+    /// observer_position is the barycentric position of the observer in meters. Output is a unit vector.
+    pub fn annual_parallax(direction: DVec3, parallax: f64, observer_position: DVec3) -> DVec3 {
+        let parallax_rad = (parallax / 3600000.).to_radians();
+
+        (direction - (observer_position / AU * parallax_rad)).normalize()
+    }
+
+    /// Deflects the unit vector direction of a star by the gravity of the sun, observer_heliocentric is the position of the observer relative to the sun in meters.
+    /// Directions close to the sun are softened in the same way as SOFA iauLdsun so that the function never blows up.
+    pub fn solar_light_deflection(direction: DVec3, observer_heliocentric: DVec3) -> DVec3 {
+        let distance = observer_heliocentric.length() / AU;
+        let sun_to_observer = observer_heliocentric.normalize();
+
+        //Limit for the deflection close to the sun
+        let limit = 0.000001 / distance.powf(2.).max(1.);
+
+        let q_dot_q_plus_e = direction.dot(direction + sun_to_observer);
+        let w = SCHWARZSCHILD_RADIUS_SUN / distance / q_dot_q_plus_e.max(limit);
+
+        (direction + (direction.cross(sun_to_observer.cross(direction)) * w)).normalize()
+    }
+
+    /// Applies annual aberration (relativistic, SOFA iauAb) to the unit vector direction of a star,
+    /// observer_velocity is the barycentric velocity of the observer in meters/second and sun_distance the distance of the observer from the sun in meters.
+    pub fn stellar_aberration(
+        direction: DVec3,
+        observer_velocity: DVec3,
+        sun_distance: f64,
+    ) -> DVec3 {
+        let v = observer_velocity / SPEED_OF_LIGHT;
+        let inverse_lorentz = (1. - v.length_squared()).sqrt();
+
+        let p_dot_v = direction.dot(v);
+        let w1 = 1. + (p_dot_v / (1. + inverse_lorentz));
+        let w2 = SCHWARZSCHILD_RADIUS_SUN / (sun_distance / AU);
+
+        ((direction * inverse_lorentz) + (v * w1) + ((v - (direction * p_dot_v)) * w2)).normalize()
+    }
+
+    /// Geocentric apparent direction (GCRS) as a unit vector of a star with astrometry at epoch (Julian year) observed at julian_date (TT).
+    /// If light_deflection is true the deflection of light by the sun is included.
+    pub fn apparent_direction_gcrs(
+        astrometry: AstrometricParameters,
+        epoch: f64,
+        julian_date: f64,
+        light_deflection: bool,
+    ) -> DVec3 {
+        let propagated = propagate_epoch(astrometry, epoch, julian_date_to_epoch(julian_date));
+        let direction = unit_vector(propagated.right_ascension, propagated.declination);
+
+        let earth_position = earth_barycentric_position(julian_date);
+        let earth_heliocentric = earth_heliocentric_position(julian_date);
+
+        let mut direction = annual_parallax(direction, propagated.parallax, earth_position);
+        if light_deflection {
+            direction = solar_light_deflection(direction, earth_heliocentric);
+        }
+
+        stellar_aberration(
+            direction,
+            earth_barycentric_velocity(julian_date),
+            earth_heliocentric.length(),
+        )
+    }
+
+    /// Apparent place of a star with astrometry at epoch (Julian year) observed from the geocenter at julian_date (TT).
+    /// Output is a 2-dimensional vector with right ascension and declination in degrees referred to the true equator and equinox of date in that order.
+    pub fn apparent_place(
+        astrometry: AstrometricParameters,
+        epoch: f64,
+        julian_date: f64,
+        light_deflection: bool,
+    ) -> DVec2 {
+        spherical_angles(icrs_to_true_of_date(
+            apparent_direction_gcrs(astrometry, epoch, julian_date, light_deflection),
+            julian_date_to_epoch(julian_date),
+        ))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        //Angle in radians between two directions that stays accurate for tiny angles
+        fn angle(a: DVec3, b: DVec3) -> f64 {
+            a.cross(b).length().atan2(a.dot(b))
+        }
+
+        #[test]
+        fn apparent_place_of_theta_persei() {
+            //Meeus, Astronomical Algorithms, example 23.a at 2028 November 13.19 TD, parallax is ignored as in the book
+            let theta_persei =
+                AstrometricParameters::new(0., 41.0499417, 49.2284667, 0.03425 * 15., -0.0895, 0.);
+
+            let apparent = apparent_place(theta_persei, 2000., 2462088.69, false);
+
+            let cos_dec = apparent.y.to_radians().cos();
+            assert!((apparent.x - 41.5599583).abs() * cos_dec * 3600. < 0.06);
+            assert!((apparent.y - 49.3520694).abs() * 3600. < 0.06);
+        }
+
+        #[test]
+        fn aberration_is_at_most_about_twenty_arcseconds() {
+            let velocity = DVec3::new(0., 29780., 0.);
+            let perpendicular = stellar_aberration(DVec3::X, velocity, AU);
+            let parallel = stellar_aberration(DVec3::Y, velocity, AU);
+
+            let shift = angle(perpendicular, DVec3::X).to_degrees() * 3600.;
+            assert!((shift - 20.49).abs() < 0.01);
+            assert!(angle(parallel, DVec3::Y) < 1e-15);
+        }
+
+        #[test]
+        fn light_deflection_at_the_limb_and_at_ninety_degrees() {
+            let observer = DVec3::new(-AU, 0., 0.);
+
+            //About 1.75 arcseconds at the solar limb
+            let limb = (959.63 / 3600_f64).to_radians();
+            let direction = DVec3::new(limb.cos(), limb.sin(), 0.);
+            let deflection = angle(solar_light_deflection(direction, observer), direction);
+            assert!((deflection.to_degrees() * 3600. - 1.75).abs() < 0.01);
+
+            //About 4 mas at 90 degrees from the sun
+            let deflection = angle(solar_light_deflection(DVec3::Y, observer), DVec3::Y);
+            assert!((deflection.to_degrees() * 3600000. - 4.07).abs() < 0.05);
+        }
+
+        #[test]
+        fn annual_parallax_of_a_star_at_one_parsec() {
+            let observer = DVec3::new(0., AU, 0.);
+            let shifted = annual_parallax(DVec3::X, 1000., observer);
+
+            assert!((angle(shifted, DVec3::X).to_degrees() * 3600. - 1.).abs() < 1e-9);
+            assert!(shifted.y < 0.);
+        }
+    }
+}
+
+/// Observatories on the surface of the Earth, geodetic coordinates on the WGS84 ellipsoid and the motion of the observer caused by the rotation of the Earth.
+/// Polar motion and UT1 - UTC are neglected, which is well below a meter and a few mm/s.
+pub mod observatory {
+    use super::precession::true_of_date_to_icrs;
+    use super::time::{greenwich_apparent_sidereal_time, julian_date_to_epoch, utc_to_tt};
+    use glam::f64::{DMat3, DVec3};
+
+    /// Equatorial radius of the WGS84 ellipsoid in meters.
+    pub const WGS84_EQUATORIAL_RADIUS: f64 = 6378137.;
+
+    /// Flattening of the WGS84 ellipsoid.
+    pub const WGS84_FLATTENING: f64 = 1. / 298.257223563;
+
+    /// Angular velocity of the rotation of the Earth in rad/s.
+    pub const EARTH_ANGULAR_VELOCITY: f64 = 7.292115855e-5;
+
+    /// Geodetic position of an observatory, longitude is in degrees positive east of Greenwich, latitude in degrees and height above the ellipsoid in meters.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Observatory {
+        pub longitude: f64,
+        pub latitude: f64,
+        pub height: f64,
+    }
+
+    impl Observatory {
+        /// Creates a new observatory, see [`Observatory`] for the units.
+        pub fn new(longitude: f64, latitude: f64, height: f64) -> Self {
+            Self {
+                longitude,
+                latitude,
+                height,
+            }
+        }
+    }
+
+    /// Earth-fixed (ITRS) position in meters of a point with geodetic longitude and latitude in degrees and height in meters on the WGS84 ellipsoid.
+    /// Output is a 3-dimensional vector with x, y and z in that order all in meters.
+    pub fn geodetic_to_itrs(longitude: f64, latitude: f64, height: f64) -> DVec3 {
+        let e_sq = WGS84_FLATTENING * (2. - WGS84_FLATTENING);
+        let latitude = latitude.to_radians();
+        let longitude = longitude.to_radians();
+
+        //Radius of curvature in the prime vertical
+        let n = WGS84_EQUATORIAL_RADIUS / (1. - (e_sq * latitude.sin().powf(2.))).sqrt();
+
+        DVec3::new(
+            (n + height) * latitude.cos() * longitude.cos(),
+            (n + height) * latitude.cos() * longitude.sin(),
+            ((n * (1. - e_sq)) + height) * latitude.sin(),
+        )
+    }
+
+    //Rotation from the Earth-fixed frame to the true equator and equinox of date
+    fn sidereal_rotation(julian_date_utc: f64) -> DMat3 {
+        DMat3::from_rotation_z(greenwich_apparent_sidereal_time(julian_date_utc).to_radians())
+    }
+
+    /// Geocentric position in meters of the observatory in the ICRS (GCRS) at julian_date_utc.
+    pub fn observatory_gcrs_position(observatory: &Observatory, julian_date_utc: f64) -> DVec3 {
+        let itrs = geodetic_to_itrs(
+            observatory.longitude,
+            observatory.latitude,
+            observatory.height,
+        );
+        let epoch = julian_date_to_epoch(utc_to_tt(julian_date_utc));
+
+        true_of_date_to_icrs(sidereal_rotation(julian_date_utc) * itrs, epoch)
+    }
+
+    /// Geocentric velocity in meters/second of the observatory in the ICRS (GCRS) at julian_date_utc, caused by the rotation of the Earth.
+    pub fn observatory_gcrs_velocity(observatory: &Observatory, julian_date_utc: f64) -> DVec3 {
+        let itrs = geodetic_to_itrs(
+            observatory.longitude,
+            observatory.latitude,
+            observatory.height,
+        );
+        let epoch = julian_date_to_epoch(utc_to_tt(julian_date_utc));
+
+        let true_of_date = sidereal_rotation(julian_date_utc) * itrs;
+        let rotation_velocity = DVec3::Z.cross(true_of_date) * EARTH_ANGULAR_VELOCITY;
+
+        true_of_date_to_icrs(rotation_velocity, epoch)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn geodetic_to_itrs_matches_sofa() {
+            //SOFA iauGd2gc on WGS84 with longitude 3.1 rad, latitude -0.5 rad and height 2500 m
+            let itrs = geodetic_to_itrs(3.1_f64.to_degrees(), -0.5_f64.to_degrees(), 2500.);
+
+            assert!((itrs.x - -5599000.557704994).abs() < 1e-6);
+            assert!((itrs.y - 233011.67223479203).abs() < 1e-6);
+            assert!((itrs.z - -3040909.470698336).abs() < 1e-6);
+        }
+
+        #[test]
+        fn rotation_velocity_of_an_observatory() {
+            let equator = Observatory::new(-70., 0., 0.);
+            let speed = observatory_gcrs_velocity(&equator, 2460000.5).length();
+            assert!((speed - (WGS84_EQUATORIAL_RADIUS * EARTH_ANGULAR_VELOCITY)).abs() < 1e-6);
+
+            let pole = Observatory::new(0., 90., 0.);
+            assert!(observatory_gcrs_velocity(&pole, 2460000.5).length() < 1e-6);
+
+            let position = observatory_gcrs_position(&equator, 2460000.5);
+            assert!((position.length() - WGS84_EQUATORIAL_RADIUS).abs() < 1e-6);
+        }
+    }
+}
+
+/// Barycentric correction of measured radial velocities, so that spectroscopic radial velocities can be used as radial_velocity in [`super::velocity::velocity`].
+/// Includes the orbital motion of the Earth from [`super::ephemeris`] and the rotation of the Earth at the observatory, good to about 1 m/s.
+/// Gravitational redshifts and the light travel time across the solar system are not included.
+pub mod barycentric {
+    use super::astrometry::SPEED_OF_LIGHT;
+    use super::coordinate_transforms::unit_vector;
+    use super::ephemeris::{earth_barycentric_position, earth_barycentric_velocity};
+    use super::observatory::{observatory_gcrs_position, observatory_gcrs_velocity, Observatory};
+    use super::time::utc_to_tt;
+    use glam::f64::DVec3;
+
+    /// Barycentric position in meters of the observatory in the ICRS at julian_date_utc.
+    pub fn observer_barycentric_position(observatory: &Observatory, julian_date_utc: f64) -> DVec3 {
+        earth_barycentric_position(utc_to_tt(julian_date_utc))
+            + observatory_gcrs_position(observatory, julian_date_utc)
+    }
+
+    /// Barycentric velocity in meters/second of the observatory in the ICRS at julian_date_utc.
+    pub fn observer_barycentric_velocity(observatory: &Observatory, julian_date_utc: f64) -> DVec3 {
+        earth_barycentric_velocity(utc_to_tt(julian_date_utc))
+            + observatory_gcrs_velocity(observatory, julian_date_utc)
+    }
+
+    /// Barycentric correction in meters/second for a target at right_ascension and declination in degrees (ICRS) observed from observatory at julian_date_utc.
+    /// This is the component of the velocity of the observer towards the target, positive when the observer moves towards the target.
+    pub fn barycentric_correction(
+        observatory: &Observatory,
+        julian_date_utc: f64,
+        right_ascension: f64,
+        declination: f64,
+    ) -> f64 {
+        observer_barycentric_velocity(observatory, julian_date_utc)
+            .dot(unit_vector(right_ascension, declination))
+    }
+
+    /// Barycentric radial velocity in km/s from a radial velocity measured_radial_velocity in km/s observed from observatory at julian_date_utc,
+    /// for a target at right_ascension and declination in degrees (ICRS). The velocities are combined relativistically to first order,
+    /// rv + correction + rv * correction / c.
+    pub fn barycentric_radial_velocity(
+        observatory: &Observatory,
+        julian_date_utc: f64,
+        right_ascension: f64,
+        declination: f64,
+        measured_radial_velocity: f64,
+    ) -> f64 {
+        let correction =
+            barycentric_correction(observatory, julian_date_utc, right_ascension, declination);
+        let measured = measured_radial_velocity * 1000.;
+
+        (measured + correction + (measured * correction / SPEED_OF_LIGHT)) / 1000.
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::coordinate_transforms::spherical_angles;
+        use crate::ephemeris::earth_barycentric_velocity;
+
+        #[test]
+        fn correction_towards_the_apex_and_the_ecliptic_pole() {
+            let observatory = Observatory::new(-17.88, 28.76, 2396.);
+            let julian_date = 2460400.5;
+
+            //Largest positive correction towards the direction the Earth is moving in
+            let apex = spherical_angles(earth_barycentric_velocity(utc_to_tt(julian_date)));
+            let correction = barycentric_correction(&observatory, julian_date, apex.x, apex.y);
+            assert!(correction > 29000. && correction < 30700.);
+
+            //The Earth moves in the ecliptic so only the rotation and the wobble of the sun remain
+            let pole = barycentric_correction(&observatory, julian_date, 270., 66.56);
+            assert!(pole.abs() < 500.);
+        }
+
+        #[test]
+        fn radial_velocity_is_combined_relativistically() {
+            let observatory = Observatory::new(0., 45., 0.);
+            let julian_date = 2455000.5;
+            let correction = barycentric_correction(&observatory, julian_date, 120., -30.);
+
+            let rv = barycentric_radial_velocity(&observatory, julian_date, 120., -30., 50.);
+            let expected =
+                50. + (correction / 1000.) + (50000. * correction / SPEED_OF_LIGHT / 1000.);
+            assert!((rv - expected).abs() < 1e-12);
+        }
+    }
+}
+
+/// Horizon coordinates for an observer on the Earth, local sidereal time and atmospheric refraction.
+/// Altitude is in degrees above the horizon and azimuth in degrees from north through east, right ascension and declination are ICRS unless stated otherwise.
+/// Aberration (up to 20 arcseconds) is neglected, use [`super::apparent::apparent_place`] with [`equatorial_of_date_to_horizontal`] when it matters.
+pub mod horizon {
+    use super::coordinate_transforms::spherical_angles;
+    use super::ephemeris::earth_heliocentric_position;
+    use super::observatory::{observatory_gcrs_position, Observatory};
+    use super::precession::icrs_to_true_of_date_angles;
+    use super::time::{
+        greenwich_apparent_sidereal_time, greenwich_mean_sidereal_time, julian_date_to_epoch,
+        utc_to_tt,
+    };
+    use glam::f64::{DVec2, DVec3};
+
+    /// Pressure in hPa and temperature in degrees Celsius of the air at the observer, used for refraction.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct Atmosphere {
+        pub pressure: f64,
+        pub temperature: f64,
+    }
+
+    impl Atmosphere {
+        /// Standard atmosphere for which the refraction formula is tabulated (1010 hPa and 10 degrees Celsius).
+        pub const STANDARD: Self = Self {
+            pressure: 1010.,
+            temperature: 10.,
+        };
+
+        /// Creates a new atmosphere, see [`Atmosphere`] for the units.
+        pub fn new(pressure: f64, temperature: f64) -> Self {
+            Self {
+                pressure,
+                temperature,
+            }
+        }
+    }
+
+    /// Local mean sidereal time in degrees at longitude in degrees (positive east) at julian_date_utc.
+    pub fn local_mean_sidereal_time(longitude: f64, julian_date_utc: f64) -> f64 {
+        (greenwich_mean_sidereal_time(julian_date_utc) + longitude).rem_euclid(360.)
+    }
+
+    /// Local apparent sidereal time in degrees at longitude in degrees (positive east) at julian_date_utc.
+    pub fn local_sidereal_time(longitude: f64, julian_date_utc: f64) -> f64 {
+        (greenwich_apparent_sidereal_time(julian_date_utc) + longitude).rem_euclid(360.)
+    }
+
+    /// Hour angle in degrees (-180 to 180, positive west of the meridian) of right_ascension in degrees referred to the true equator and equinox of date.
+    pub fn hour_angle(right_ascension: f64, longitude: f64, julian_date_utc: f64) -> f64 {
+        (local_sidereal_time(longitude, julian_date_utc) - right_ascension + 180.).rem_euclid(360.)
+            - 180.
+    }
+
+    /// Refraction in degrees to add to a true (geometric) altitude in degrees, Saemundsson's formula scaled for pressure and temperature.
+    /// Returns 0 for altitudes more than a degree below the horizon where the formula is not valid.
+    pub fn refraction(altitude: f64, atmosphere: Atmosphere) -> f64 {
+        if altitude < -1. {
+            return 0.;
+        }
+
+        let standard = 1.02 / (altitude + (10.3 / (altitude + 5.11))).to_radians().tan();
+
+        standard * (atmosphere.pressure / 1010.) * (283. / (273. + atmosphere.temperature)) / 60.
+    }
+
+    /// Converts hour_angle and declination in degrees to horizon coordinates for an observer at latitude in degrees.
+    /// Output is a 2-dimensional vector with altitude and azimuth in that order.
+    pub fn hour_angle_to_horizontal(hour_angle: f64, declination: f64, latitude: f64) -> DVec2 {
+        let h = hour_angle.to_radians();
+        let dec = declination.to_radians();
+        let lat = latitude.to_radians();
+
+        let altitude = ((lat.sin() * dec.sin()) + (lat.cos() * dec.cos() * h.cos())).asin();
+        let azimuth = (-dec.cos() * h.sin())
+            .atan2((dec.sin() * lat.cos()) - (dec.cos() * lat.sin() * h.cos()));
+
+        DVec2::new(altitude.to_degrees(), azimuth.to_degrees().rem_euclid(360.))
+    }
+
+    /// Converts altitude and azimuth in degrees for an observer at latitude in degrees to hour angle and declination.
+    /// Output is a 2-dimensional vector with hour angle (-180 to 180) and declination in that order.
+    pub fn horizontal_to_hour_angle(altitude: f64, azimuth: f64, latitude: f64) -> DVec2 {
+        let alt = altitude.to_radians();
+        let az = azimuth.to_radians();
+        let lat = latitude.to_radians();
+
+        let declination = ((lat.sin() * alt.sin()) + (lat.cos() * alt.cos() * az.cos())).asin();
+        let hour_angle = (-alt.cos() * az.sin())
+            .atan2((alt.sin() * lat.cos()) - (alt.cos() * lat.sin() * az.cos()));
+
+        DVec2::new(hour_angle.to_degrees(), declination.to_degrees())
+    }
+
+    /// Horizon coordinates of right_ascension and declination in degrees referred to the true equator and equinox of date
+    /// seen from observatory at julian_date_utc, with refraction if an atmosphere is given.
+    /// Output is a 2-dimensional vector with altitude and azimuth in that order.
+    pub fn equatorial_of_date_to_horizontal(
+        observatory: &Observatory,
+        julian_date_utc: f64,
+        right_ascension: f64,
+        declination: f64,
+        atmosphere: Option<Atmosphere>,
+    ) -> DVec2 {
+        let hour_angle = hour_angle(right_ascension, observatory.longitude, julian_date_utc);
+        let mut horizontal =
+            hour_angle_to_horizontal(hour_angle, declination, observatory.latitude);
+
+        if let Some(atmosphere) = atmosphere {
+            horizontal.x += refraction(horizontal.x, atmosphere);
+        }
+
+        horizontal
+    }
+
+    /// Horizon coordinates of a star at right_ascension and declination in degrees (ICRS) seen from observatory at julian_date_utc,
+    /// with refraction if an atmosphere is given. Output is a 2-dimensional vector with altitude and azimuth in that order.
+    pub fn horizontal(
+        observatory: &Observatory,
+        julian_date_utc: f64,
+        right_ascension: f64,
+        declination: f64,
+        atmosphere: Option<Atmosphere>,
+    ) -> DVec2 {
+        let epoch = julian_date_to_epoch(utc_to_tt(julian_date_utc));
+        let of_date = icrs_to_true_of_date_angles(right_ascension, declination, epoch);
+
+        equatorial_of_date_to_horizontal(
+            observatory,
+            julian_date_utc,
+            of_date.x,
+            of_date.y,
+            atmosphere,
+        )
+    }
+
+    /// Horizon coordinates of an object at position in meters relative to the sun in the ICRS (e.g. from [`super::position::position`])
+    /// seen from observatory at julian_date_utc, including the parallax of the observer. Output is a 2-dimensional vector with altitude and azimuth in that order.
+    pub fn horizontal_position(
+        observatory: &Observatory,
+        julian_date_utc: f64,
+        position: DVec3,
+        atmosphere: Option<Atmosphere>,
+    ) -> DVec2 {
+        let observer = earth_heliocentric_position(utc_to_tt(julian_date_utc))
+            + observatory_gcrs_position(observatory, julian_date_utc);
+        let angles = spherical_angles(position - observer);
+
+        horizontal(observatory, julian_date_utc, angles.x, angles.y, atmosphere)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::time::julian_date;
+
+        //Meeus, Astronomical Algorithms, example 13.b: Venus from the US Naval Observatory in Washington
+        fn washington() -> Observatory {
+            Observatory::new(-77.0656, 38.9214, 0.)
+        }
+
+        #[test]
+        fn horizontal_coordinates_of_venus() {
+            let julian_date = julian_date(1987, 4, 10, 19, 21, 0.);
+
+            let hour_angle = hour_angle(347.3193375, washington().longitude, julian_date);
+            //Meeus uses the IAU 1982 sidereal time which differs by a fraction of an arcsecond
+            assert!((hour_angle - 64.352133).abs() < 3e-4);
+
+            //Meeus measures azimuth from the south, 68.0337 degrees
+            let horizontal = equatorial_of_date_to_horizontal(
+                &washington(),
+                julian_date,
+                347.3193375,
+                -6.7198917,
+                None,
+            );
+            assert!((horizontal.x - 15.1249).abs() < 3e-4);
+            assert!((horizontal.y - 248.0337).abs() < 3e-4);
+        }
+
+        #[test]
+        fn local_sidereal_time_is_greenwich_plus_longitude() {
+            let julian_date = 2446895.5;
+            let greenwich = greenwich_apparent_sidereal_time(julian_date);
+
+            assert!((local_sidereal_time(0., julian_date) - greenwich).abs() < 1e-12);
+            assert!(
+                (local_sidereal_time(-77.0656, julian_date) - (greenwich - 77.0656)).abs() < 1e-9
+            );
+            assert!(
+                (local_mean_sidereal_time(10., julian_date)
+                    - local_sidereal_time(10., julian_date))
+                .abs()
+                    < 0.01
+            );
+        }
+
+        #[test]
+        fn horizontal_round_trip_and_cardinal_points() {
+            let horizontal = hour_angle_to_horizontal(-37.5, 12.25, 52.1);
+            let back = horizontal_to_hour_angle(horizontal.x, horizontal.y, 52.1);
+            assert!((back - DVec2::new(-37.5, 12.25)).length() < 1e-10);
+
+            //The celestial pole is due north at the altitude of the latitude
+            let pole = hour_angle_to_horizontal(0., 90., 52.1);
+            assert!((pole.x - 52.1).abs() < 1e-10);
+
+            //Rising objects are in the east and setting objects in the west
+            assert!((hour_angle_to_horizontal(-90., 0., 52.1).y - 90.).abs() < 1e-10);
+            assert!((hour_angle_to_horizontal(90., 0., 52.1).y - 270.).abs() < 1e-10);
+        }
+
+        #[test]
+        fn refraction_with_saemundssons_formula() {
+            //About 29 arcminutes at the horizon, 5.4 at ten degrees and nothing at the zenith
+            assert!((refraction(0., Atmosphere::STANDARD) * 60. - 28.98).abs() < 0.01);
+            assert!((refraction(10., Atmosphere::STANDARD) * 60. - 5.41).abs() < 0.01);
+            assert!(refraction(90., Atmosphere::STANDARD).abs() * 3600. < 0.2);
+
+            //Proportional to the density of the air
+            let dense = Atmosphere::new(2020., 10.);
+            assert!(
+                (refraction(5., dense) - (2. * refraction(5., Atmosphere::STANDARD))).abs() < 1e-15
+            );
+            assert!(
+                refraction(5., Atmosphere::new(1010., 40.)) < refraction(5., Atmosphere::STANDARD)
+            );
+
+            assert_eq!(refraction(-2., Atmosphere::STANDARD), 0.);
+        }
+
+        #[test]
+        fn refraction_lifts_the_horizontal_altitude() {
+            let observatory = Observatory::new(0., 50., 0.);
+            let julian_date = 2460000.5;
+
+            let geometric = horizontal(&observatory, julian_date, 60., 10., None);
+            let apparent = horizontal(
+                &observatory,
+                julian_date,
+                60.,
+                10.,
+                Some(Atmosphere::STANDARD),
+            );
+
+            assert_eq!(geometric.y, apparent.y);
+            assert!(
+                (apparent.x - geometric.x - refraction(geometric.x, Atmosphere::STANDARD)).abs()
+                    < 1e-15
+            );
+        }
+    }
+}
+
+/// Rise, transit and set times of stars and the windows in which they are observable from an observatory, for planning observations.
+/// Times are Julian dates in UTC, right ascension and declination are ICRS and in degrees. The position of date is taken at the start of the search,
+/// precession during a night is far below a second of time. Altitudes are geometric, use -0.5667 degrees for the rise and set of a star including standard refraction.
+pub mod visibility {
+    use super::coordinate_transforms::spherical_angles;
+    use super::ephemeris::earth_heliocentric_position;
+    use super::horizon::{horizontal, hour_angle};
+    use super::observatory::Observatory;
+    use super::precession::icrs_to_true_of_date_angles;
+    use super::time::{julian_date_to_epoch, utc_to_tt};
+    use glam::f64::DVec2;
+
+    /// Rate of the sidereal time in degrees per day of UT.
+    pub const SIDEREAL_RATE: f64 = 360.98564736629;
+
+    /// Altitude in degrees of the center of a star at its rise and set including standard refraction.
+    pub const STANDARD_ALTITUDE: f64 = -0.5667;
+
+    /// Altitude in degrees of the center of the sun at sunset and sunrise including standard refraction and the radius of the sun.
+    pub const SUNSET_ALTITUDE: f64 = -0.8333;
+
+    /// Altitude in degrees of the center of the sun at the end and start of astronomical twilight.
+    pub const ASTRONOMICAL_TWILIGHT_ALTITUDE: f64 = -18.;
+
+    /// Rise, upper culmination (transit) and set of a star as Julian dates in UTC.
+    /// rise and set are None when the star never crosses the altitude, in which case either circumpolar or never_rises is true.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct RiseTransitSet {
+        pub rise: Option<f64>,
+        pub transit: f64,
+        pub set: Option<f64>,
+        pub transit_altitude: f64,
+        pub circumpolar: bool,
+        pub never_rises: bool,
+    }
+
+    //Right ascension and declination of date at julian_date_utc
+    fn of_date(right_ascension: f64, declination: f64, julian_date_utc: f64) -> DVec2 {
+        icrs_to_true_of_date_angles(
+            right_ascension,
+            declination,
+            julian_date_to_epoch(utc_to_tt(julian_date_utc)),
+        )
+    }
+
+    /// Airmass at altitude in degrees (Kasten and Young 1989), finite down to the horizon.
+    pub fn airmass(altitude: f64) -> f64 {
+        1. / (altitude.to_radians().sin() + (0.50572 * (altitude + 6.07995).powf(-1.6364)))
+    }
+
+    /// Altitude in degrees at which the airmass equals airmass, the inverse of [`airmass`] found by bisection.
+    pub fn airmass_to_altitude(airmass_limit: f64) -> f64 {
+        let mut low = 0.;
+        let mut high = 90.;
+        for _i in 0..60 {
+            let middle = (low + high) / 2.;
+            if airmass(middle) > airmass_limit {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+
+        (low + high) / 2.
+    }
+
+    /// Hour angle in degrees (0 to 180) at which a star at declination in degrees crosses altitude in degrees for an observer at latitude in degrees.
+    /// Returns 180 if the star is always above the altitude and 0 if it never reaches it, this includes an observer at a pole
+    /// and a star at a celestial pole whose altitude does not change with the hour angle.
+    pub fn hour_angle_at_altitude(altitude: f64, declination: f64, latitude: f64) -> f64 {
+        let dec = declination.to_radians();
+        let lat = latitude.to_radians();
+        let sin_altitude = altitude.to_radians().sin();
+
+        let cos_lat_cos_dec = lat.cos() * dec.cos();
+        if cos_lat_cos_dec.abs() < 1e-12 {
+            return if lat.sin() * dec.sin() >= sin_altitude {
+                180.
+            } else {
+                0.
+            };
+        }
+
+        let cos_hour_angle = (sin_altitude - (lat.sin() * dec.sin())) / cos_lat_cos_dec;
+
+        cos_hour_angle.clamp(-1., 1.).acos().to_degrees()
+    }
+
+    /// Altitude in degrees of a star at declination in degrees when it transits the meridian for an observer at latitude in degrees.
+    pub fn transit_altitude(declination: f64, latitude: f64) -> f64 {
+        90. - (latitude - declination).abs()
+    }
+
+    /// First upper culmination (transit) after julian_date_utc of a star at right_ascension and declination in degrees (ICRS) seen from observatory,
+    /// together with the rise before and the set after it when the star crosses altitude in degrees.
+    /// The sun is not taken into account, so the transit can be in daylight. Use [`rise_transit_set`] for the transit during a given night.
+    pub fn next_rise_transit_set(
+        observatory: &Observatory,
+        julian_date_utc: f64,
+        right_ascension: f64,
+        declination: f64,
+        altitude: f64,
+    ) -> RiseTransitSet {
+        let of_date = of_date(right_ascension, declination, julian_date_utc);
+        let hour_angle_now = hour_angle(of_date.x, observatory.longitude, julian_date_utc);
+        let transit = julian_date_utc + ((-hour_angle_now).rem_euclid(360.) / SIDEREAL_RATE);
+
+        let transit_altitude = transit_altitude(of_date.y, observatory.latitude);
+        let never_rises = transit_altitude < altitude;
+        let circumpolar = !never_rises
+            && (hour_angle_at_altitude(altitude, of_date.y, observatory.latitude) >= 180.);
+
+        let (rise, set) = if never_rises || circumpolar {
+            (None, None)
+        } else {
+            let half_arc =
+                hour_angle_at_altitude(altitude, of_date.y, observatory.latitude) / SIDEREAL_RATE;
+            (Some(transit - half_arc), Some(transit + half_arc))
+        };
+
+        RiseTransitSet {
+            rise,
+            transit,
+            set,
+            transit_altitude,
+            circumpolar,
+            never_rises,
+        }
+    }
+
+    /// Geometric altitude in degrees of the center of the sun seen from observatory at julian_date_utc, without refraction.
+    pub fn sun_altitude(observatory: &Observatory, julian_date_utc: f64) -> f64 {
+        let sun = spherical_angles(-earth_heliocentric_position(utc_to_tt(julian_date_utc)));
+
+        horizontal(observatory, julian_date_utc, sun.x, sun.y, None).x
+    }
+
+    //Time between start and end where the sun crosses sun_altitude_limit, found by bisection
+    fn sun_crossing(
+        observatory: &Observatory,
+        start: f64,
+        end: f64,
+        sun_altitude_limit: f64,
+    ) -> f64 {
+        let below_at_start = sun_altitude(observatory, start) < sun_altitude_limit;
+        let mut low = start;
+        let mut high = end;
+        for _i in 0..40 {
+            let middle = (low + high) / 2.;
+            if (sun_altitude(observatory, middle) < sun_altitude_limit) == below_at_start {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+
+        (low + high) / 2.
+    }
+
+    /// The first night after julian_date_utc seen from observatory, where night is the time the sun is below sun_altitude_limit in degrees
+    /// (e.g. [`SUNSET_ALTITUDE`] or [`ASTRONOMICAL_TWILIGHT_ALTITUDE`]). Pass local noon to get the night that starts that evening,
+    /// if the sun is already below the limit at julian_date_utc the night starts there.
+    /// Output is the start and end of the night as Julian dates in UTC, None if the sun does not go below the limit within a day (midnight sun).
+    /// During polar night the night is cut off one day after it starts.
+    pub fn night(
+        observatory: &Observatory,
+        julian_date_utc: f64,
+        sun_altitude_limit: f64,
+    ) -> Option<(f64, f64)> {
+        //Ten minute steps are far shorter than any twilight
+        let step = 1. / 144.;
+        let is_dark = |time: f64| sun_altitude(observatory, time) < sun_altitude_limit;
+
+        let mut start = if is_dark(julian_date_utc) {
+            Some(julian_date_utc)
+        } else {
+            None
+        };
+        let mut time = julian_date_utc;
+        while start.is_none() && time < julian_date_utc + 1. {
+            if is_dark(time + step) {
+                start = Some(sun_crossing(
+                    observatory,
+                    time,
+                    time + step,
+                    sun_altitude_limit,
+                ));
+            }
+            time += step;
+        }
+        let start = start?;
+
+        let mut time = start;
+        while time < start + 1. {
+            if !is_dark(time + step) {
+                return Some((
+                    start,
+                    sun_crossing(observatory, time, time + step, sun_altitude_limit),
+                ));
+            }
+            time += step;
+        }
+
+        Some((start, start + 1.))
+    }
+
+    /// Rise, upper culmination (transit) and set of a star at right_ascension and declination in degrees (ICRS) seen from observatory
+    /// on the night after julian_date_utc, see [`night`] for the night and sun_altitude_limit. The transit is the one closest to the middle of the night,
+    /// the rise and set around it are for the star crossing altitude in degrees and can fall outside the night.
+    /// Output is the rise, transit and set together with the start and end of the night as Julian dates in UTC, None if there is no night.
+    /// Use the night with [`windows_above_altitude`] for the time the star can actually be observed.
+    pub fn rise_transit_set(
+        observatory: &Observatory,
+        julian_date_utc: f64,
+        right_ascension: f64,
+        declination: f64,
+        altitude: f64,
+        sun_altitude_limit: f64,
+    ) -> Option<(RiseTransitSet, (f64, f64))> {
+        let night = night(observatory, julian_date_utc, sun_altitude_limit)?;
+        let middle = (night.0 + night.1) / 2.;
+        let half_sidereal_day = 180. / SIDEREAL_RATE;
+
+        Some((
+            next_rise_transit_set(
+                observatory,
+                middle - half_sidereal_day,
+                right_ascension,
+                declination,
+                altitude,
+            ),
+            night,
+        ))
+    }
+
+    /// Windows between start and end (Julian dates in UTC) in which a star at right_ascension and declination in degrees (ICRS) is above altitude in degrees seen from observatory.
+    /// Output is a vector of (start, end) pairs in Julian dates in UTC.
+    pub fn windows_above_altitude(
+        observatory: &Observatory,
+        start: f64,
+        end: f64,
+        right_ascension: f64,
+        declination: f64,
+        altitude: f64,
+    ) -> std::vec::Vec<(f64, f64)> {
+        let of_date = of_date(right_ascension, declination, start);
+        let half_arc =
+            hour_angle_at_altitude(altitude, of_date.y, observatory.latitude) / SIDEREAL_RATE;
+
+        let mut windows = std::vec::Vec::new();
+        if transit_altitude(of_date.y, observatory.latitude) < altitude || end <= start {
+            return windows;
+        }
+        if half_arc * SIDEREAL_RATE >= 180. {
+            windows.push((start, end));
+            return windows;
+        }
+
+        //Walk through the transits from the one before start
+        let sidereal_day = 360. / SIDEREAL_RATE;
+        let hour_angle_start = hour_angle(of_date.x, observatory.longitude, start);
+        let mut transit =
+            start + ((-hour_angle_start).rem_euclid(360.) / SIDEREAL_RATE) - sidereal_day;
+
+        while transit - half_arc < end {
+            let window_start = (transit - half_arc).max(start);
+            let window_end = (transit + half_arc).min(end);
+            if window_end > window_start {
+                windows.push((window_start, window_end));
+            }
+            transit += sidereal_day;
+        }
+
+        windows
+    }
+
+    /// Windows between start and end (Julian dates in UTC) in which a star at right_ascension and declination in degrees (ICRS) is below airmass_limit seen from observatory.
+    /// Output is a vector of (start, end) pairs in Julian dates in UTC.
+    pub fn windows_below_airmass(
+        observatory: &Observatory,
+        start: f64,
+        end: f64,
+        right_ascension: f64,
+        declination: f64,
+        airmass_limit: f64,
+    ) -> std::vec::Vec<(f64, f64)> {
+        windows_above_altitude(
+            observatory,
+            start,
+            end,
+            right_ascension,
+            declination,
+            airmass_to_altitude(airmass_limit),
+        )
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::time::julian_date;
+
+        fn la_palma() -> Observatory {
+            Observatory::new(-17.88, 28.76, 2396.)
+        }
+
+        //Geometric altitude of a star in degrees
+        fn altitude(observatory: &Observatory, julian_date_utc: f64, ra: f64, dec: f64) -> f64 {
+            horizontal(observatory, julian_date_utc, ra, dec, None).x
+        }
+
+        #[test]
+        fn hour_angle_at_the_poles() {
+            //Observer at the north pole, the altitude is the declination all the time
+            assert_eq!(hour_angle_at_altitude(0., 30., 90.), 180.);
+            assert_eq!(hour_angle_at_altitude(45., 30., 90.), 0.);
+            assert_eq!(hour_angle_at_altitude(0., -30., -90.), 180.);
+
+            //Star at the celestial pole, the altitude is the latitude all the time
+            assert_eq!(hour_angle_at_altitude(0., 90., 40.), 180.);
+            assert_eq!(hour_angle_at_altitude(0., 90., -40.), 0.);
+            assert_eq!(hour_angle_at_altitude(0., -90., -40.), 180.);
+
+            //On the equator a star on the celestial equator is up for half a day
+            assert!((hour_angle_at_altitude(0., 0., 0.) - 90.).abs() < 1e-12);
+        }
+
+        #[test]
+        fn next_rise_transit_set_of_sirius() {
+            let start = julian_date(2024, 1, 10, 12, 0, 0.);
+            let (ra, dec) = (101.28715533, -16.71611586);
+            let times = next_rise_transit_set(&la_palma(), start, ra, dec, STANDARD_ALTITUDE);
+
+            assert!(times.transit > start && times.transit < start + 1.);
+            assert!(!times.circumpolar && !times.never_rises);
+
+            let transit_altitude = altitude(&la_palma(), times.transit, ra, dec);
+            assert!((transit_altitude - times.transit_altitude).abs() < 0.01);
+            assert!((transit_altitude - (90. - 28.76 - 16.72)).abs() < 0.05);
+
+            //Precession and nutation over the night are far below the tolerance
+            assert!(
+                (altitude(&la_palma(), times.rise.unwrap(), ra, dec) - STANDARD_ALTITUDE).abs()
+                    < 0.01
+            );
+            assert!(
+                (altitude(&la_palma(), times.set.unwrap(), ra, dec) - STANDARD_ALTITUDE).abs()
+                    < 0.01
+            );
+        }
+
+        #[test]
+        fn circumpolar_and_never_rising_stars() {
+            let start = julian_date(2024, 1, 10, 12, 0, 0.);
+
+            let polaris = next_rise_transit_set(&la_palma(), start, 37.95, 89.26, 0.);
+            assert!(polaris.circumpolar && polaris.rise.is_none() && polaris.set.is_none());
+
+            let canopus =
+                next_rise_transit_set(&Observatory::new(10., 60., 0.), start, 95.99, -52.7, 0.);
+            assert!(canopus.never_rises && canopus.rise.is_none());
+        }
+
+        #[test]
+        fn night_at_la_palma_on_the_summer_solstice() {
+            let noon = julian_date(2024, 6, 21, 13, 0, 0.);
+            let (start, end) = night(&la_palma(), noon, SUNSET_ALTITUDE).unwrap();
+
+            assert!((sun_altitude(&la_palma(), start) - SUNSET_ALTITUDE).abs() < 1e-6);
+            assert!((sun_altitude(&la_palma(), end) - SUNSET_ALTITUDE).abs() < 1e-6);
+            //About ten hours between sunset and sunrise
+            assert!(((end - start) * 24. - 10.1).abs() < 0.2);
+
+            let (dark_start, dark_end) =
+                night(&la_palma(), noon, ASTRONOMICAL_TWILIGHT_ALTITUDE).unwrap();
+            assert!(dark_start > start && dark_end < end);
+        }
+
+        #[test]
+        fn no_night_under_the_midnight_sun() {
+            let tromso = Observatory::new(18.96, 69.65, 0.);
+            let noon = julian_date(2024, 6, 21, 11, 0, 0.);
+
+            assert!(night(&tromso, noon, SUNSET_ALTITUDE).is_none());
+            assert!(rise_transit_set(&tromso, noon, 0., 0., 0., SUNSET_ALTITUDE).is_none());
+        }
+
+        #[test]
+        fn rise_transit_set_on_a_night() {
+            //Vega in June transits around local midnight
+            let noon = julian_date(2024, 6, 21, 13, 0, 0.);
+            let (ra, dec) = (279.23473479, 38.78368896);
+            let (times, (start, end)) = rise_transit_set(
+                &la_palma(),
+                noon,
+                ra,
+                dec,
+                30.,
+                ASTRONOMICAL_TWILIGHT_ALTITUDE,
+            )
+            .unwrap();
+
+            assert!(times.transit > start && times.transit < end);
+            let middle = (start + end) / 2.;
+            assert!((times.transit - middle).abs() < 0.5 * 360. / SIDEREAL_RATE);
+
+            //The windows inside the night end where the night or the altitude limit ends
+            let windows = windows_above_altitude(&la_palma(), start, end, ra, dec, 30.);
+            assert_eq!(windows.len(), 1);
+            assert!((windows[0].0 - times.rise.unwrap().max(start)).abs() < 1e-9);
+            assert!((windows[0].1 - times.set.unwrap().min(end)).abs() < 1e-9);
+        }
+
+        #[test]
+        fn airmass_windows_match_altitude_windows() {
+            let start = julian_date(2024, 1, 10, 12, 0, 0.);
+            let end = start + 3.;
+            let (ra, dec) = (101.28715533, -16.71611586);
+
+            let by_airmass = windows_below_airmass(&la_palma(), start, end, ra, dec, 2.);
+            let by_altitude =
+                windows_above_altitude(&la_palma(), start, end, ra, dec, airmass_to_altitude(2.));
+            assert_eq!(by_airmass, by_altitude);
+            assert_eq!(by_airmass.len(), 3);
 
-    /// Calculates total right ascension in degrees with right_ascension_h, right_ascension_min and right_ascension_s in hours, minutes and seconds respectively.
-    pub fn right_ascension_total(
-        right_ascension_h: f64,
-        right_ascension_min: f64,
-        right_ascension_s: f64,
-    ) -> f64 {
-        (right_ascension_h * 15.)
-            + (right_ascension_min * (1. / 4.))
-            + (right_ascension_s * (1. / 240.))
+            let circumpolar = windows_above_altitude(&la_palma(), start, end, 37.95, 89.26, 10.);
+            assert_eq!(circumpolar, vec![(start, end)]);
+        }
+
+        #[test]
+        fn kasten_young_airmass() {
+            //Zenith angles of 0, 60, 80 and 90 degrees
+            assert!((airmass(90.) - 0.9997).abs() < 1e-4);
+            assert!((airmass(30.) - 1.9943).abs() < 1e-4);
+            assert!((airmass(10.) - 5.5860).abs() < 1e-4);
+            assert!((airmass(0.) - 37.92).abs() < 0.01);
+        }
+
+        #[test]
+        fn airmass_to_altitude_is_the_inverse() {
+            for limit in [1.2, 1.5, 2., 3., 10.] {
+                assert!((airmass(airmass_to_altitude(limit)) - limit).abs() < 1e-9);
+            }
+        }
     }
+}
 
-    /// Calculates r min or the minimum distance between the primary and companion boides in a twobody system also known as perigee
-    /// (suffix may change depending on what object it reffers to).
-    /// Output is just the x coordinate in the ellipses plane in au.
-    pub fn perigee(a: f64, e: f64) -> f64 {
-        a * (1. - e)
+/// Spatial index over cartesian positions such as those from [`super::position::position`], for neighbourhood queries like all stars within 10 pc of a star.
+/// Distances are in the same unit as the positions (meters for positions from `spv-rs`).
+pub mod spatial {
+    use glam::f64::DVec3;
+    use std::cmp::Ordering;
+    use std::collections::BinaryHeap;
+
+    /// Balanced k-d tree over a set of 3D positions. Results refer to the positions by their index in the slice the tree was built from.
+    #[derive(Debug, Clone)]
+    pub struct KdTree {
+        points: std::vec::Vec<DVec3>,
+        //Indices into points laid out as an implicit tree, the node of a range is its middle element
+        indices: std::vec::Vec<usize>,
+        //Split axis of the node at the same place in indices
+        axes: std::vec::Vec<usize>,
     }
 
-    /// Calculates r max or the maximum distance between the primary and companion boides in a twobody system also known as apogee
-    /// (suffix may change depending on what object it reffers to).
-    /// Output is just the x coordinate in the ellipses plane in au.
-    pub fn apogee(a: f64, e: f64) -> f64 {
-        a * (1. + e)
+    //Neighbour candidate ordered by squared distance for the heap of the k-nearest search
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Candidate {
+        distance_sq: f64,
+        index: usize,
     }
 
-    /// Calculates r min or the minimum distance between the primary and companion boides in a twobody system also known as perigee
-    /// (suffix may change depending on what object it reffers to).
-    /// Output is 3-dimensional vector that represents the coordinates for perigee rotated to be relative to the earth/sun plane in meters.
-    pub fn relative_perigee(a: f64, e: f64, lotn: f64, aop: f64, i: f64) -> DVec3 {
-        let a_si = au_to_m(a);
-        let x = a_si * (1. - e);
+    impl Eq for Candidate {}
 
-        let euler_angle_transformations = euler_angle_transformations(lotn, aop, i).to_cols_array();
-        let x1 = euler_angle_transformations[0];
-        let x2 = euler_angle_transformations[1];
-        let x3 = euler_angle_transformations[2];
+    impl PartialOrd for Candidate {
+        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
 
-        DVec3::new(x * x1, x * x2, x * x3)
+    impl Ord for Candidate {
+        fn cmp(&self, other: &Self) -> Ordering {
+            self.distance_sq
+                .total_cmp(&other.distance_sq)
+                .then(self.index.cmp(&other.index))
+        }
     }
 
-    /// Calculates r max or the maximum distance between the primary and companion boides in a twobody system also known as apogee
-    /// (suffix may change depending on what object it reffers to).
-    /// Output is 3-dimensional vector that represents the coordinates for apogee rotated to be relative to the earth/sun plane in meters.
-    pub fn relative_apogee(a: f64, e: f64, lotn: f64, aop: f64, i: f64) -> DVec3 {
-        let a_si = au_to_m(a);
-        let x = a_si * (1. + e);
+    impl KdTree {
+        /// Builds the tree from positions, the positions are copied so the slice can be dropped afterwards.
+        pub fn new(positions: &[DVec3]) -> Self {
+            let mut tree = Self {
+                points: positions.to_vec(),
+                indices: (0..positions.len()).collect(),
+                axes: vec![0; positions.len()],
+            };
+            tree.build(0, positions.len());
+
+            tree
+        }
 
-        let euler_angle_transformations = euler_angle_transformations(lotn, aop, i).to_cols_array();
-        let x1 = euler_angle_transformations[0];
-        let x2 = euler_angle_transformations[1];
-        let x3 = euler_angle_transformations[2];
+        /// Number of positions in the tree.
+        pub fn len(&self) -> usize {
+            self.points.len()
+        }
 
-        DVec3::new(x * x1, x * x2, x * x3)
-    }
+        /// True if the tree holds no positions.
+        pub fn is_empty(&self) -> bool {
+            self.points.is_empty()
+        }
 
-    /// Calculates the eccentric anomaly in degrees
-    pub fn eccentric_anomaly(e: f64, period: f64, t_p: f64) -> f64 {
-        //SI units
-        let p_si = period * 31557600.;
-        let t_p_si = t_p * 31557600.;
+        fn build(&mut self, low: usize, high: usize) {
+            if high <= low {
+                return;
+            }
 
-        //Defining angles
-        let mean_anom = std::f64::consts::PI * 2. * t_p_si / p_si;
-        let mut ecc_anom = mean_anom;
-        for _i in (0..=20).step_by(1) {
-            ecc_anom = mean_anom + (e * ecc_anom.sin());
+            //Split along the axis with the largest spread
+            let mut min = DVec3::splat(f64::INFINITY);
+            let mut max = DVec3::splat(f64::NEG_INFINITY);
+            for index in &self.indices[low..high] {
+                min = min.min(self.points[*index]);
+                max = max.max(self.points[*index]);
+            }
+            let spread = max - min;
+            let axis = if spread.x >= spread.y && spread.x >= spread.z {
+                0
+            } else if spread.y >= spread.z {
+                1
+            } else {
+                2
+            };
+
+            let middle = (low + high) / 2;
+            let points = &self.points;
+            self.indices[low..high].select_nth_unstable_by(middle - low, |a, b| {
+                points[*a][axis].total_cmp(&points[*b][axis])
+            });
+            self.axes[middle] = axis;
+
+            self.build(low, middle);
+            self.build(middle + 1, high);
         }
 
-        ecc_anom
-    }
+        /// Nearest position to point. Output is the index and the distance, or None if the tree is empty.
+        pub fn nearest(&self, point: DVec3) -> Option<(usize, f64)> {
+            self.k_nearest(point, 1).into_iter().next()
+        }
 
-    /// Calculates the true anomaly in degrees
-    pub fn true_anomaly(e: f64, period: f64, t_p: f64) -> f64 {
-        //SI units
-        let p_si = period * 31557600.;
-        let t_p_si = t_p * 31557600.;
+        /// The k nearest positions to point. Output is a vector of indices and distances sorted from the closest.
+        pub fn k_nearest(&self, point: DVec3, k: usize) -> std::vec::Vec<(usize, f64)> {
+            let mut heap = BinaryHeap::with_capacity(k + 1);
+            if k > 0 {
+                self.search_k_nearest(point, k, 0, self.points.len(), &mut heap);
+            }
 
-        //Defining angles
-        let mean_anom = std::f64::consts::PI * 2. * t_p_si / p_si;
-        let mut ecc_anom = mean_anom;
-        for _i in (0..=20).step_by(1) {
-            ecc_anom = mean_anom + (e * ecc_anom.sin());
+            heap.into_sorted_vec()
+                .into_iter()
+                .map(|candidate| (candidate.index, candidate.distance_sq.sqrt()))
+                .collect()
         }
 
-        2. * (((1. + e) / (1. - e)).sqrt() * (ecc_anom * 0.5).tan()).atan()
-    }
+        fn search_k_nearest(
+            &self,
+            point: DVec3,
+            k: usize,
+            low: usize,
+            high: usize,
+            heap: &mut BinaryHeap<Candidate>,
+        ) {
+            if high <= low {
+                return;
+            }
 
-    /// Calculates the flight path angle for the companion body in degrees
-    pub fn flight_path_angle(e: f64, period: f64, t_p: f64) -> f64 {
-        //SI units
-        let p_si = period * 31557600.;
-        let t_p_si = t_p * 31557600.;
+            let middle = (low + high) / 2;
+            let index = self.indices[middle];
+            let candidate = Candidate {
+                distance_sq: self.points[index].distance_squared(point),
+                index,
+            };
+            if heap.len() < k {
+                heap.push(candidate);
+            } else if candidate < *heap.peek().unwrap() {
+                heap.pop();
+                heap.push(candidate);
+            }
 
-        //Defining angles
-        let mean_anom = std::f64::consts::PI * 2. * t_p_si / p_si;
-        let mut ecc_anom = mean_anom;
-        for _i in (0..=20).step_by(1) {
-            ecc_anom = mean_anom + (e * ecc_anom.sin());
+            //Search the side of the point first and the other side only if it can hold something closer
+            let axis = self.axes[middle];
+            let offset = point[axis] - self.points[index][axis];
+            let (near, far) = if offset < 0. {
+                ((low, middle), (middle + 1, high))
+            } else {
+                ((middle + 1, high), (low, middle))
+            };
+
+            self.search_k_nearest(point, k, near.0, near.1, heap);
+            if heap.len() < k || offset.powf(2.) < heap.peek().unwrap().distance_sq {
+                self.search_k_nearest(point, k, far.0, far.1, heap);
+            }
         }
 
-        ((e * ecc_anom.sin()) / ((1. - ((e.powf(2.)) * (ecc_anom.cos().powf(2.)))).sqrt()))
-            .asin()
-            .to_degrees()
-    }
+        /// All positions within radius of point. Output is a vector of indices and distances sorted from the closest.
+        pub fn within_radius(&self, point: DVec3, radius: f64) -> std::vec::Vec<(usize, f64)> {
+            let mut found = std::vec::Vec::new();
+            self.search_radius(point, radius.powf(2.), 0, self.points.len(), &mut found);
 
-    /// Calculates the semi parameter for a twobody system and returns it in meters.
-    pub fn semi_parameter(a: f64, e: f64) -> f64 {
-        let a_si = au_to_m(a);
-        let b_si = semi_minor_axis(a, e);
+            found.sort_unstable();
+            found
+                .into_iter()
+                .map(|candidate| (candidate.index, candidate.distance_sq.sqrt()))
+                .collect()
+        }
 
-        (b_si.powf(2.)) / a_si
-    }
+        fn search_radius(
+            &self,
+            point: DVec3,
+            radius_sq: f64,
+            low: usize,
+            high: usize,
+            found: &mut std::vec::Vec<Candidate>,
+        ) {
+            if high <= low {
+                return;
+            }
 
-    /// Calculates the semi minor axis for a twobody system and returns it in meters.
-    pub fn semi_minor_axis(a: f64, e: f64) -> f64 {
-        let a_si = au_to_m(a);
+            let middle = (low + high) / 2;
+            let index = self.indices[middle];
+            let distance_sq = self.points[index].distance_squared(point);
+            if distance_sq <= radius_sq {
+                found.push(Candidate { distance_sq, index });
+            }
 
-        a_si * ((1. - e.powf(2.)).sqrt())
+            let axis = self.axes[middle];
+            let offset = point[axis] - self.points[index][axis];
+            if offset <= 0. || offset.powf(2.) <= radius_sq {
+                self.search_radius(point, radius_sq, low, middle, found);
+            }
+            if offset >= 0. || offset.powf(2.) <= radius_sq {
+                self.search_radius(point, radius_sq, middle + 1, high, found);
+            }
+        }
     }
 
-    /// Calculates the total radius for a twobody system
-    pub fn radius(a: f64, e: f64, period: f64, t_p: f64) -> f64 {
-        let nu = true_anomaly(e, period, t_p);
-        let p = semi_parameter(a, e);
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use rand::{Rng, SeedableRng};
+        use rand_chacha::ChaCha8Rng;
+
+        //Points in a cube with a side of 20 pc, flattened along z like a disk
+        fn random_points(count: usize, seed: u64) -> std::vec::Vec<DVec3> {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            (0..count)
+                .map(|_| {
+                    let point = DVec3::new(rng.gen(), rng.gen(), rng.gen::<f64>() * 0.1) - 0.5;
+                    point * 6.2e17
+                })
+                .collect()
+        }
 
-        p / (1. + (e * nu.cos()))
-    }
+        fn brute_force(points: &[DVec3], point: DVec3) -> std::vec::Vec<(usize, f64)> {
+            let mut distances: std::vec::Vec<(usize, f64)> = points
+                .iter()
+                .enumerate()
+                .map(|(index, position)| (index, position.distance(point)))
+                .collect();
+            distances.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
 
-    /// Calculates the specific angular momentum value
-    pub fn specific_angular_momentum_value(a: f64, e: f64, period: f64) -> f64 {
-        let p = semi_parameter(a, e);
-        let mu = standard_gravitational_parameter(a, period);
+            distances
+        }
 
-        (mu * p).sqrt()
-    }
+        #[test]
+        fn nearest_and_k_nearest_match_brute_force() {
+            let points = random_points(2000, 1);
+            let tree = KdTree::new(&points);
+            assert_eq!(tree.len(), 2000);
 
-    /// Calculates the specific angular momentum coordinates
-    pub fn specific_angular_momentum_coordinates(
-        a: f64,
-        e: f64,
-        period: f64,
-        t_p: f64,
-        lotn: f64,
-        aop: f64,
-        i: f64,
-    ) -> DVec3 {
-        let r = companion_relative_position(a, e, period, t_p, lotn, aop, i);
-        let v = companion_relative_velocity(a, e, period, t_p, lotn, aop, i);
+            for point in random_points(50, 2) {
+                let expected = brute_force(&points, point);
 
-        DVec3::cross(r, v)
-    }
+                assert_eq!(tree.nearest(point), Some(expected[0]));
+                assert_eq!(tree.k_nearest(point, 10), expected[..10].to_vec());
+            }
+        }
 
-    /// Calculates the stadard gravitational parameter
-    pub fn standard_gravitational_parameter(a: f64, period: f64) -> f64 {
-        let period_si = period * 31557600.;
-        let a_si = au_to_m(a);
+        #[test]
+        fn within_radius_matches_brute_force() {
+            let points = random_points(2000, 3);
+            let tree = KdTree::new(&points);
 
-        ((a_si.powf(3.)) * 4. * (std::f64::consts::PI.powf(2.))) / (period_si.powf(2.))
-    }
+            //Queries from inside the set, like all stars within 2 pc of a star
+            for index in [0, 17, 999, 1999] {
+                let expected: std::vec::Vec<(usize, f64)> = brute_force(&points, points[index])
+                    .into_iter()
+                    .filter(|(_, distance)| *distance <= 6.2e16)
+                    .collect();
 
-    /// Specific mechanical energy (used by other equation but exposed here if you need it)
-    pub fn specific_mechanical_energy(a: f64, period: f64) -> f64 {
-        let a_si = au_to_m(a);
-        let mu = standard_gravitational_parameter(a, period);
+                assert_eq!(expected[0], (index, 0.));
+                assert_eq!(tree.within_radius(points[index], 6.2e16), expected);
+            }
+        }
 
-        0. - (mu / (2. * a_si))
+        #[test]
+        fn degenerate_trees_and_queries() {
+            let empty = KdTree::new(&[]);
+            assert!(empty.is_empty());
+            assert_eq!(empty.nearest(DVec3::ZERO), None);
+            assert!(empty.within_radius(DVec3::ZERO, 1.).is_empty());
+
+            //Duplicated positions and more neighbours asked for than there are positions
+            let points = [DVec3::ONE, DVec3::ZERO, DVec3::ONE, DVec3::X];
+            let tree = KdTree::new(&points);
+            assert!(tree.k_nearest(DVec3::ZERO, 0).is_empty());
+            assert_eq!(
+                tree.k_nearest(DVec3::ONE, 10),
+                brute_force(&points, DVec3::ONE)
+            );
+            assert_eq!(tree.within_radius(DVec3::ONE, 0.).len(), 2);
+        }
     }
+}
 
-    /// If you dind't have the period already
-    pub fn period(a: f64, mu: f64) -> f64 {
-        let a_si = au_to_m(a);
-
-        2. * std::f64::consts::PI * (((a_si.powf(3.)) / mu).sqrt())
+/// Ephemerides of visual binaries in the quantities double star observers measure, separation rho in arcseconds and position angle theta in degrees
+/// east of north, together with the offsets of the companion from the primary in right ascension (times cos(declination)) and declination in arcseconds.
+/// The elements follow the usual visual binary convention where lotn is the position angle of the ascending node, i < 90 is direct (theta increasing) motion
+/// and the reference frame of [`super::coordinate_transforms::euler_angle_transformations`] has x towards north and y towards east.
+/// Dates and the epoch of periastron are Julian years, Besselian epochs can be converted with [`super::time::besselian_to_julian_epoch`].
+pub mod visual_binary {
+    use super::common::{is_parabolic, true_anomaly};
+    use super::coordinate_transforms::euler_angle_transformations;
+    use super::orbit::{OrbitalElements, OrbitalElementsError};
+
+    /// Predicted relative position of the companion at epoch (Julian year), separation, delta_ra and delta_dec are in arcseconds and position_angle in degrees.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct RelativePosition {
+        pub epoch: f64,
+        pub separation: f64,
+        pub position_angle: f64,
+        pub delta_ra: f64,
+        pub delta_dec: f64,
     }
 
-    /// If you for some reason had these parameters and not a then here ya go
-    pub fn semi_major_axis(
-        standard_gravitational_parameter: f64,
-        specific_mechanical_energy: f64,
-    ) -> f64 {
-        0. - (standard_gravitational_parameter / (2. * specific_mechanical_energy))
+    /// Thiele-Innes constants A, B, F and G in the unit of a (e.g. arcseconds) for lotn, aop and i in degrees, in that order.
+    /// They are the north and east components of the x and y collums of [`euler_angle_transformations`] scaled by a.
+    pub fn thiele_innes(a: f64, lotn: f64, aop: f64, i: f64) -> [f64; 4] {
+        let euler_angle_transformations = euler_angle_transformations(lotn, aop, i);
+
+        [
+            a * euler_angle_transformations.x_axis.x,
+            a * euler_angle_transformations.x_axis.y,
+            a * euler_angle_transformations.y_axis.x,
+            a * euler_angle_transformations.y_axis.y,
+        ]
     }
 
-    /// Mean motion or n
-    pub fn mean_motion(a: f64, period: f64) -> f64 {
-        let a_si = au_to_m(a);
-        let mu = standard_gravitational_parameter(a, period);
+    /// Relative positions for every date in dates (Julian years) where the a of elements is the angular semi major-axis in arcseconds
+    /// (the angular periastron distance for parabolic orbits) and periastron_epoch is the epoch of periastron passage in Julian years.
+    /// Unbound orbits are supported like everywhere else in `spv-rs`, with period the time scale of [`super::common::mean_anomaly`].
+    pub fn ephemeris_angular(
+        elements: &OrbitalElements,
+        periastron_epoch: f64,
+        dates: &[f64],
+    ) -> std::vec::Vec<RelativePosition> {
+        let [a, b, f, g] = thiele_innes(
+            elements.a().abs(),
+            elements.lotn(),
+            elements.aop(),
+            elements.i(),
+        );
+        let e = elements.e();
+
+        //Semi parameter in units of |a|, 1 - e^2 for elliptic, e^2 - 1 for hyperbolic and 2 for parabolic orbits where a is the periastron distance
+        let p = if is_parabolic(e) {
+            2.
+        } else {
+            (1. - e.powf(2.)).abs()
+        };
 
-        (mu / (a_si.powf(3.))).sqrt()
+        dates
+            .iter()
+            .map(|epoch| {
+                let v = true_anomaly(e, elements.period(), epoch - periastron_epoch);
+
+                //Coordinates in the orbital plane in units of |a|, x towards periastron, from the radius of the conic
+                let r = p / (1. + (e * v.cos()));
+                let x = r * v.cos();
+                let y = r * v.sin();
+
+                let delta_dec = (a * x) + (f * y);
+                let delta_ra = (b * x) + (g * y);
+
+                RelativePosition {
+                    epoch: *epoch,
+                    separation: (delta_dec.powf(2.) + delta_ra.powf(2.)).sqrt(),
+                    position_angle: delta_ra.atan2(delta_dec).to_degrees().rem_euclid(360.),
+                    delta_ra,
+                    delta_dec,
+                }
+            })
+            .collect()
     }
 
-    /// If you for some reason had these parameters and not e then here ya go.
-    pub fn eccentricity(
-        standard_gravitational_parameter: f64,
-        specific_mechanical_energy: f64,
-        specific_angular_momentum_value: f64,
-    ) -> f64 {
-        (1. - ((2. * specific_mechanical_energy * (specific_angular_momentum_value.powf(2.)))
-            / (standard_gravitational_parameter.powf(2.))))
-        .sqrt()
-    }
+    /// Relative positions for every date in dates (Julian years) where the a of elements is in au and parallax is in mas (milliarcseconds),
+    /// periastron_epoch is the epoch of periastron passage in Julian years.
+    /// Fails if the parallax does not give a valid angular semi major-axis, e.g. if it is zero or not finite.
+    pub fn ephemeris(
+        elements: &OrbitalElements,
+        parallax: f64,
+        periastron_epoch: f64,
+        dates: &[f64],
+    ) -> Result<std::vec::Vec<RelativePosition>, OrbitalElementsError> {
+        let angular = elements.with_a(elements.a() * parallax / 1000.)?;
 
-    /// Distance between one foci and the center of the ellipse in au.
-    pub fn linear_eccentricity(a: f64, e: f64) -> f64 {
-        a * e
+        Ok(ephemeris_angular(&angular, periastron_epoch, dates))
     }
 
-    /// Flattening is another way to defining eccentricity for an ellipse.
-    pub fn flattening(a: f64, e: f64) -> f64 {
-        let a_si = au_to_m(a);
-        let b = semi_minor_axis(a_si, e);
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::common::au_to_m;
+        use crate::galactic_potential::GM_SUN;
+        use crate::orbit::Orbit;
+
+        //Elliptic, parabolic and hyperbolic orbits with periastron at 10 au
+        fn orbits() -> [OrbitalElements; 3] {
+            [0.5, 1., 1.5].map(|e| {
+                OrbitalElements::from_periastron(10., e, 2. * GM_SUN, 205., 232., 79.).unwrap()
+            })
+        }
 
-        (a_si - b) / a_si
-    }
+        #[test]
+        fn ephemeris_matches_the_orbit_in_the_sky() {
+            //At 100 mas one au is 0.1 arcseconds, x is towards north and y towards east
+            let parallax = 100.;
+            let dates = [1990., 1999.5, 2000., 2003.7, 2025.];
 
-    /// Parallax to parsec convert function where the parallax parameter is in mas (milliarcseconds).
-    pub fn parallax_to_parsec(parallax: f64) -> f64 {
-        1. / (parallax / 1000.)
-    }
+            for elements in orbits() {
+                let orbit = Orbit::new(elements);
+                let ephemeris = ephemeris(&elements, parallax, 2000., &dates).unwrap();
 
-    /// Simple function motsly used by `spv-rs` to convert au to meters (used for semi major-axis in most cases but can be used for other stuff too).
-    pub fn au_to_m(a: f64) -> f64 {
-        a * 1000. * 149597870.7
-    }
+                for position in ephemeris {
+                    let relative = orbit.position_at(position.epoch - 2000.) / au_to_m(1.) * 0.1;
 
-    ///
-    pub fn apparent_mag_to_absolute_mag(parallax: f64, apparent_magnitude: f64) -> f64 {
-        apparent_magnitude + 5. * (parallax.log10() + 1.)
-    }
+                    assert!((position.delta_dec - relative.x).abs() < 1e-12);
+                    assert!((position.delta_ra - relative.y).abs() < 1e-12);
+                    assert!((position.separation - relative.x.hypot(relative.y)).abs() < 1e-12);
+                }
+            }
+        }
 
-    ///
-    pub fn temperature(b_v_index: f64) -> f64 {
-        4600. * ((1. / ((0.92 * b_v_index) + 1.7)) + (1. / ((0.92 * b_v_index) + 0.62)))
-    }
-}
+        #[test]
+        fn face_on_circular_orbit() {
+            //Constant separation and a position angle that increases by 90 degrees every quarter of a period
+            let elements = OrbitalElements::new(2., 0., 40., 30., 20., 0.).unwrap();
+            let ephemeris = ephemeris_angular(&elements, 2000., &[2000., 2010., 2020.]);
 
-/// Transform fucntions used by `spv-rs` but exposed her if you want to use them yourself.
-pub mod coordinate_transforms {
-    use glam::f64::{DMat3, DVec3};
+            for (index, position) in ephemeris.iter().enumerate() {
+                assert!((position.separation - 2.).abs() < 1e-12);
+                assert!((position.position_angle - (50. + (90. * index as f64))).abs() < 1e-9);
+            }
+        }
 
-    /// Method for getting base manipulation matrix that is used to rotate the companion star in a twobody system
-    /// relative to the earth/sun plane.
-    /// lotn is Longitude of the node (Omega) in degrees, aop is Argument of periastron (omega) in degrees and finally i is the Inclination in degrees.
-    /// Output is a 3-dimensional matrix with x1, x2 and x3 in the first collum, y1, y2 and y3 in the second collum and z1, z2 and z3 in the third collum.
-    pub fn euler_angle_transformations(lotn: f64, aop: f64, i: f64) -> DMat3 {
-        //In rad
-        let lotn_rad = lotn.to_radians();
-        let aop_rad = aop.to_radians();
-        let i_rad = i.to_radians();
+        #[test]
+        fn unbound_orbits_pass_periastron_at_the_periastron_distance() {
+            //Face-on, so the separation is the distance
+            let parabola = OrbitalElements::new(3., 1., 10., 0., 0., 0.).unwrap();
+            let hyperbola = OrbitalElements::new(-3., 1.5, 10., 0., 0., 0.).unwrap();
+
+            assert!(
+                (ephemeris_angular(&parabola, 2000., &[2000.])[0].separation - 3.).abs() < 1e-12
+            );
+            assert!(
+                (ephemeris_angular(&hyperbola, 2000., &[2000.])[0].separation - 1.5).abs() < 1e-12
+            );
+
+            //And move away on both sides of periastron
+            let far = ephemeris_angular(&hyperbola, 2000., &[1900., 2100.]);
+            assert!(far[0].separation > 10. && far[1].separation > 10.);
+            assert!(far[0].separation.is_finite() && far[1].separation.is_finite());
+        }
 
-        //Ellipse base
-        let x1 = (lotn_rad.cos() * aop_rad.cos()) - (lotn_rad.sin() * i_rad.cos() * aop_rad.sin());
-        let x2 = (lotn_rad.sin() * aop_rad.cos()) + (lotn_rad.cos() * i_rad.cos() * aop_rad.sin());
-        let x3 = i_rad.sin() * aop_rad.sin();
+        #[test]
+        fn thiele_innes_of_a_face_on_orbit() {
+            //A and G are a * cos(lotn + aop), F = -B = -a * sin(lotn + aop)
+            let [a, b, f, g] = thiele_innes(2., 30., 20., 0.);
+            let angle = 50_f64.to_radians();
 
-        let y1 = ((0. - lotn_rad.cos()) * aop_rad.sin())
-            - (lotn_rad.sin() * i_rad.cos() * aop_rad.cos());
-        let y2 = ((0. - lotn_rad.sin()) * aop_rad.sin())
-            + (lotn_rad.cos() * i_rad.cos() * aop_rad.cos());
-        let y3 = i_rad.sin() * aop_rad.cos();
+            assert!((a - (2. * angle.cos())).abs() < 1e-12);
+            assert!((b - (2. * angle.sin())).abs() < 1e-12);
+            assert!((f + (2. * angle.sin())).abs() < 1e-12);
+            assert!((g - (2. * angle.cos())).abs() < 1e-12);
+        }
 
-        let z1 = i_rad.sin() * lotn_rad.sin();
-        let z2 = (0. - i_rad.sin()) * lotn_rad.cos();
-        let z3 = i_rad.cos();
+        #[test]
+        fn ephemeris_rejects_invalid_parallaxes() {
+            let elements = orbits()[0];
 
-        DMat3::from_cols(
-            DVec3::new(x1, x2, x3),
-            DVec3::new(y1, y2, y3),
-            DVec3::new(z1, z2, z3),
-        )
+            assert_eq!(
+                ephemeris(&elements, 0., 2000., &[2000.]),
+                Err(OrbitalElementsError::SemiMajorAxis)
+            );
+            assert!(ephemeris(&elements, f64::NAN, 2000., &[2000.]).is_err());
+        }
     }
 }
 
@@ -796,41 +6710,196 @@ pub mod nbss {
     */
 }
 
-// WIP
-/*
-pub fn above(
-    radius_primary: f64,
-    observers_longitude_primary: f64,
-    observers_latitude_primary: f64,
-    obliquity_of_the_ecliptic_primary: f64,
-    rotation_rate_primary: f64,
-    lotn_primary: f64,
-    aop_primary: f64,
-    i_primary: f64,
-    distance: f64,
-    a_companion: f64,
-    e_companion: f64,
-    period_companion: f64,
-    t_p_companion: f64,
-    lotn_companion: f64,
-    aop_companion: f64,
-    i_companion: f64,
+/// The companion of a twobody system as seen by an observer standing on the surface of the rotating primary.
+/// The spin axis of the primary is given as right ascension and declination of its north pole in the same reference frame as the
+/// orbital elements of the companion (the frame of [`super::coordinate_transforms::euler_angle_transformations`]).
+pub mod above {
+    use super::coordinate_transforms::unit_vector;
+    use super::orbit::{Orbit, OrbitalElements};
+    use super::position::position_surface;
+    use glam::f64::{DMat3, DVec3};
+
+    /// Rotating primary, radius is in meters, pole_right_ascension and pole_declination are the direction of the north pole of the spin axis in degrees,
+    /// rotation_period is in years (the same unit as the period of the companion) and prime_meridian is the rotation angle in degrees at periastron of the companion.
+    /// The prime meridian is measured from the ascending node of the equator of the primary on the reference plane, as for the IAU rotation elements.
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct RotatingBody {
+        pub radius: f64,
+        pub pole_right_ascension: f64,
+        pub pole_declination: f64,
+        pub rotation_period: f64,
+        pub prime_meridian: f64,
+    }
+
+    impl RotatingBody {
+        /// Creates a new rotating body, see [`RotatingBody`] for the units.
+        pub fn new(
+            radius: f64,
+            pole_right_ascension: f64,
+            pole_declination: f64,
+            rotation_period: f64,
+            prime_meridian: f64,
+        ) -> Self {
+            Self {
+                radius,
+                pole_right_ascension,
+                pole_declination,
+                rotation_period,
+                prime_meridian,
+            }
+        }
+
+        /// Rotation matrix from the body-fixed frame (x towards longitude 0 on the equator, z towards the north pole) to the reference frame
+        /// at time in years since periastron of the companion.
+        pub fn body_fixed_matrix(&self, time: f64) -> DMat3 {
+            let pole = unit_vector(self.pole_right_ascension, self.pole_declination);
+
+            //Ascending node of the equator on the reference plane, any direction in the plane works if the pole is along z
+            let node = if DVec3::Z.cross(pole).length() > 1e-12 {
+                DVec3::Z.cross(pole).normalize()
+            } else {
+                DVec3::X
+            };
+            let equator = DMat3::from_cols(node, pole.cross(node), pole);
+
+            let rotation_angle = self.prime_meridian + (360. * time / self.rotation_period);
+
+            equator * DMat3::from_rotation_z(rotation_angle.to_radians())
+        }
+    }
+
+    /// Position of an observer at longitude (positive east) and latitude in degrees on the surface of primary relative to its center,
+    /// in meters in the reference frame at time in years since periastron of the companion.
+    pub fn observer_position(
+        primary: &RotatingBody,
+        longitude: f64,
+        latitude: f64,
+        time: f64,
+    ) -> DVec3 {
+        primary.body_fixed_matrix(time) * position_surface(primary.radius, longitude, latitude)
+    }
+
+    /// Where the companion appears in the sky of an observer at longitude (positive east) and latitude in degrees on the surface of primary,
+    /// for every time in times in years since periastron of the companion.
+    /// Output is a vector of 3-dimensional vectors with altitude in degrees, azimuth in degrees from north through east and distance in meters in that order.
+    ///
+    /// ```rust
+    /// use spv_rs::above::{above, RotatingBody};
+    /// use spv_rs::orbit::OrbitalElements;
+    ///
+    /// //Pole along the z-axis and a circular companion in the equatorial plane, one rotation per orbit
+    /// let primary = RotatingBody::new(7e8, 0., 90., 1., 0.);
+    /// let companion = OrbitalElements::new(1., 0., 1., 0., 0., 0.).unwrap();
+    ///
+    /// //The companion stays in the zenith of the observer below it
+    /// let sky = above(&primary, 0., 0., &companion, &[0., 0.25, 0.5]);
+    /// for position in sky {
+    ///     assert!((position.x - 90.).abs() < 1e-6);
+    /// }
+    /// ```
+    pub fn above(
+        primary: &RotatingBody,
+        longitude: f64,
+        latitude: f64,
+        companion: &OrbitalElements,
+        times: &[f64],
+    ) -> std::vec::Vec<DVec3> {
+        let orbit = Orbit::new(*companion);
+
+        times
+            .iter()
+            .map(|time| {
+                let companion_position = orbit.position_at(*time);
+                let observer = observer_position(primary, longitude, latitude, *time);
+                let pole = primary.body_fixed_matrix(*time).z_axis;
+
+                //Local frame of the observer
+                let up = observer.normalize();
+                let east = if pole.cross(up).length() > 1e-12 {
+                    pole.cross(up).normalize()
+                } else {
+                    up.any_orthonormal_vector()
+                };
+                let north = up.cross(east);
+
+                let line_of_sight = companion_position - observer;
+                let direction = line_of_sight.normalize();
+
+                DVec3::new(
+                    direction.dot(up).asin().to_degrees(),
+                    direction
+                        .dot(east)
+                        .atan2(direction.dot(north))
+                        .to_degrees()
+                        .rem_euclid(360.),
+                    line_of_sight.length(),
+                )
+            })
+            .collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::common::au_to_m;
+
+        //Pole along the z-axis, one rotation per orbit of the companion
+        fn primary() -> RotatingBody {
+            RotatingBody::new(7e8, 0., 90., 1., 0.)
+        }
+
+        //Circular companion at 1 au in the equator of the primary, at periastron it is on the x-axis
+        fn companion() -> OrbitalElements {
+            OrbitalElements::new(1., 0., 1., 0., 0., 0.).unwrap()
+        }
 
-) {
+        #[test]
+        fn companion_on_the_horizon_seen_from_the_pole() {
+            let sky = above(&primary(), 0., 90., &companion(), &[0., 0.3]);
+            let dip = -(7e8 / au_to_m(1.)).atan().to_degrees();
 
-    let observer_declination =  ((observers_latitude_primary.sin() * obliquity_of_the_ecliptic_primary.cos()) + (observers_latitude_primary.cos() * obliquity_of_the_ecliptic_primary.sin() * observers_longitude_primary.sin())).asin();
-    let observer_right_ascension = ((observers_latitude_primary.cos() * observers_longitude_primary.cos()) / observer_declination.cos()).acos();
+            for position in sky {
+                assert!((position.x - dip).abs() < 1e-9);
+                assert!((position.z - au_to_m(1.).hypot(7e8)).abs() < 1e-3);
+            }
+        }
 
-    let position_surface = position_surface(radius_primary, observer_right_ascension, observer_declination);
+        #[test]
+        fn companion_on_the_meridian_at_mid_latitude() {
+            //Due south for an observer at 45 degrees north, just below the 45 degrees of the celestial equator because of the offset of the observer
+            let sky = above(&primary(), 0., 45., &companion(), &[0.]);
+            let (d, radius) = (au_to_m(1.), 7e8);
+            let up = d * 45_f64.to_radians().cos() - radius;
+            let south = d * 45_f64.to_radians().sin();
+
+            assert!((sky[0].x - up.atan2(south).to_degrees()).abs() < 1e-9);
+            assert!((sky[0].y - 180.).abs() < 1e-9);
+            assert!((sky[0].z - up.hypot(south)).abs() < 1e-3);
+        }
 
-    let primary_rotation = euler_angle_transformations(lotn_primary, aop_primary, i_primary);
+        #[test]
+        fn companion_sets_in_the_west() {
+            //A quarter of a turn east of the sub-companion point the companion is on the western horizon
+            let sky = above(&primary(), 90., 0., &companion(), &[0.]);
+            let dip = -(7e8 / au_to_m(1.)).atan().to_degrees();
 
-    let companion_rotation = euler_angle_transformations(lotn_companion, aop_companion, i_companion);
+            assert!((sky[0].x - dip).abs() < 1e-9);
+            assert!((sky[0].y - 270.).abs() < 1e-9);
 
-    let new_companion_rotation = companion_rotation - primary_rotation;
+            //Without the synchronous rotation the primary turns under the companion
+            let slow = RotatingBody::new(7e8, 0., 90., 1e9, 90.);
+            let sky = above(&slow, 0., 0., &companion(), &[0.]);
+            assert!((sky[0].y - 270.).abs() < 1e-9);
+        }
 
-    let companion_position = companion_relative_position(a_companion, e_companion, period_companion, t_p_companion, lotn_companion, aop_companion, i_companion);
+        #[test]
+        fn tilted_pole_moves_the_sub_companion_point() {
+            //Pole tilted by 30 degrees towards the companion at periastron, which puts it over latitude 30 degrees north
+            let tilted = RotatingBody::new(7e8, 0., 60., 1., 270.);
+            let sky = above(&tilted, 0., 30., &companion(), &[0.]);
 
-    let primary_velocity = (2. * std::f64::consts::PI * radius_primary) / rotation_rate_primary;
+            assert!((sky[0].x - 90.).abs() < 0.01);
+            assert!((observer_position(&tilted, 0., 30., 0.).length() - 7e8).abs() < 1e-6);
+        }
+    }
 }
-*/